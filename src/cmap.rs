@@ -6,3 +6,4 @@ pub mod align;
 pub mod chain;
 pub mod map;
 pub mod project;
+pub mod validate;
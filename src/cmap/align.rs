@@ -1,7 +1,12 @@
-use anyhow::{Context, Result};
-use memchr::memchr;
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
 use std::str::from_utf8;
+use std::sync::OnceLock;
+
+use crate::cmap::chain::ChainId;
+use crate::error::ChaintoolsError;
 
 /// A structure to represent an alignment record.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,7 +24,8 @@ impl AlignmentRecord {
     /// * `align` - A byte array
     ///
     /// # Returns
-    /// * Vec<AlignmentRecord>
+    /// * Result<Vec<AlignmentRecord>>
+    /// * A [`ChaintoolsError::BadBlock`] error pointing to the offending line of the block
     ///
     /// # Example
     ///
@@ -30,10 +36,10 @@ impl AlignmentRecord {
     /// let data = chain::AlignmentRecord::parse(line);
     /// println!("{:?}", data);
     ///
-    /// > [AlignmentRecord { size: 9, dt: 1, dq: 0, is_last: false}];
+    /// > Ok([AlignmentRecord { size: 9, dt: 1, dq: 0, is_last: false}]);
     /// ```
-    pub fn parse(align: &[u8]) -> Vec<AlignmentRecord> {
-        Self::parse_byte(align).expect("ERROR: Failed to parse alignment record")
+    pub fn parse(align: &[u8]) -> Result<Vec<AlignmentRecord>> {
+        Ok(Self::parse_byte(align)?)
     }
 
     /// Parse a byte array from a single alignment line into an alignment record.    
@@ -43,6 +49,7 @@ impl AlignmentRecord {
     ///
     /// # Returns
     /// * Option<AlignmentRecord>
+    /// * None if the line cannot be parsed
     ///
     /// # Example
     ///
@@ -56,10 +63,7 @@ impl AlignmentRecord {
     /// > Some(AlignmentRecord { size: 9, dt: 1, dq: 0, is_last: false });
     /// ```
     pub fn from(align: &[u8]) -> Option<Self> {
-        Self::parse_byte(align)
-            .expect("Failed to parse alignment record")
-            .first()
-            .cloned()
+        Self::parse_byte(align).ok()?.first().cloned()
     }
 
     /// Create a new alignment record.
@@ -184,82 +188,368 @@ impl AlignmentRecord {
     /// * `align` - A byte array
     ///
     /// # Returns
-    /// * Result<Vec<AlignmentRecord>, Box<dyn std::error::Error>>
+    /// * Result<Vec<AlignmentRecord>, ChaintoolsError>
+    fn parse_byte(align: &[u8]) -> std::result::Result<Vec<AlignmentRecord>, ChaintoolsError> {
+        let mut acc = vec![];
+        Self::walk(align, |record| acc.push(record))?;
+        Ok(acc)
+    }
+
+    /// Walk over the alignment records of a block without collecting them.
+    ///
+    /// Lines hold either `size`, `dt` and `dq` separated by tabs or spaces, or a
+    /// single `size` for the final block, which must end the block; blank lines,
+    /// `#` comments and CRLF line endings are tolerated. Empty blocks and blocks
+    /// without a final size-only line are errors.
+    ///
+    /// # Arguments
+    /// * `align` - A byte array
+    /// * `f` - A closure receiving every record
+    ///
+    /// # Returns
+    /// * Result<usize, ChaintoolsError> - the number of records
+    pub(crate) fn walk<F>(align: &[u8], mut f: F) -> std::result::Result<usize, ChaintoolsError>
+    where
+        F: FnMut(AlignmentRecord),
+    {
+        // positions are relative to the block; readers shift them to file positions
+        let error = |(i, line_offset): (usize, usize), message: String| ChaintoolsError::BadBlock {
+            line: Some(i as u64 + 1),
+            offset: Some(line_offset as u64),
+            chain_id: None,
+            message,
+        };
+        let mut count: usize = 0;
+        let mut offset: usize = 0;
+        let mut last_seen: bool = false;
+        let mut position: (usize, usize) = (0, 0);
+        for (i, line) in align.split(|x| *x == b'\n').enumerate() {
+            let line_offset = offset;
+            offset += line.len() + 1;
+            // CRLF line endings, blank lines and comments are tolerated
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            position = (i, line_offset);
+            let error = |message: String| error(position, message);
+            if last_seen {
+                return Err(error(String::from("Unexpected line after the final block")));
+            }
+            let mut fields = line.split(|x| x.is_ascii_whitespace()).filter(|x| !x.is_empty());
+            let mut next = |name: &str| -> std::result::Result<Option<u32>, ChaintoolsError> {
+                match fields.next() {
+                    None => Ok(None),
+                    Some(field) => from_utf8(field)
+                        .ok()
+                        .and_then(|x| x.parse::<u32>().ok())
+                        .map(Some)
+                        .ok_or_else(|| error(format!(
+                            "Failed to parse {} from {:?}",
+                            name,
+                            String::from_utf8_lossy(field)
+                        ))),
+                }
+            };
+            let size = next("size")?.ok_or_else(|| error(String::from("Missing size")))?;
+            let record = match (next("dt")?, next("dq")?, next("extra field")?) {
+                (None, None, None) => {
+                    last_seen = true;
+                    AlignmentRecord { size, dt: 0, dq: 0, is_last: true }
+                }
+                (Some(dt), Some(dq), None) => AlignmentRecord { size, dt, dq, is_last: false },
+                _ => return Err(error(format!(
+                    "Expected 1 or 3 fields, got {:?}",
+                    String::from_utf8_lossy(line)
+                ))),
+            };
+            f(record);
+            count += 1;
+        }
+        match (count, last_seen) {
+            (0, _) => Err(error(position, String::from("Empty alignment block"))),
+            (_, false) => Err(error(position, String::from("Missing the final block of size only"))),
+            _ => Ok(count),
+        }
+    }
+
+    /// Parse an alignment block, fixing recoverable defects.
+    ///
+    /// Fields may be separated by any whitespace; a trailing gap after the final
+    /// block is dropped and blocks given by their size only before the final one
+    /// are assumed to have no gaps.
+    ///
+    /// # Arguments
+    /// * `align` - A byte array
+    ///
+    /// # Returns
+    /// * Result<(Vec<AlignmentRecord>, Vec<String>), ChaintoolsError> - the records and a note for every kind of fix
+    pub(crate) fn repair(align: &[u8]) -> std::result::Result<(Vec<AlignmentRecord>, Vec<String>), ChaintoolsError> {
+        let mut lines: Vec<Vec<u32>> = Vec::new();
+        let mut offset: usize = 0;
+        let mut respaced: usize = 0;
+        for (i, line) in align.split(|x| *x == b'\n').enumerate() {
+            let line_offset = offset;
+            offset += line.len() + 1;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            let mut fields: Vec<u32> = Vec::with_capacity(3);
+            for field in line.split(|x| x.is_ascii_whitespace()).filter(|x| !x.is_empty()) {
+                let value = from_utf8(field).ok().and_then(|x| x.parse::<u32>().ok());
+                match value {
+                    Some(x) => fields.push(x),
+                    None => return Err(ChaintoolsError::BadBlock {
+                        line: Some(i as u64 + 1),
+                        offset: Some(line_offset as u64),
+                        chain_id: None,
+                        message: format!("Failed to parse {:?}", String::from_utf8_lossy(field)),
+                    }),
+                }
+            }
+            if fields.len() != 1 && fields.len() != 3 {
+                return Err(ChaintoolsError::BadBlock {
+                    line: Some(i as u64 + 1),
+                    offset: Some(line_offset as u64),
+                    chain_id: None,
+                    message: format!("Expected 1 or 3 fields, got {:?}", String::from_utf8_lossy(line)),
+                });
+            }
+            let canonical = line.split(|x| *x == b'\t').count() == fields.len()
+                && line.iter().all(|x| !x.is_ascii_whitespace() || *x == b'\t');
+            if !canonical {
+                respaced += 1;
+            }
+            lines.push(fields);
+        }
+        if lines.is_empty() {
+            return Err(ChaintoolsError::BadBlock {
+                line: None,
+                offset: None,
+                chain_id: None,
+                message: String::from("Empty alignment block"),
+            });
+        }
+
+        let mut notes: Vec<String> = Vec::new();
+        if respaced > 0 {
+            notes.push(format!("normalized field separators in {} block lines", respaced));
+        }
+        let n = lines.len();
+        let mut gapless: usize = 0;
+        let mut records: Vec<AlignmentRecord> = Vec::with_capacity(n);
+        for (i, fields) in lines.iter().enumerate() {
+            let is_last = i + 1 == n;
+            let record = match (fields.as_slice(), is_last) {
+                ([size, dt, dq], false) => AlignmentRecord::new(*size, *dt, *dq, false),
+                ([size, dt, dq], true) => {
+                    notes.push(format!("dropped the trailing gap ({}, {}) after the final block", dt, dq));
+                    AlignmentRecord::new(*size, 0, 0, true)
+                }
+                ([size], false) => {
+                    gapless += 1;
+                    AlignmentRecord::new(*size, 0, 0, false)
+                }
+                (_, _) => AlignmentRecord::new(fields[0], 0, 0, true),
+            };
+            records.push(record);
+        }
+        if gapless > 0 {
+            notes.push(format!("assumed no gaps after {} size-only blocks", gapless));
+        }
+        Ok((records, notes))
+    }
+}
+
+/// Alignment records of a chain, optionally decoded on demand.
+///
+/// Chains read with [`crate::io::reader::Reader::from_file_lazy`] only keep the
+/// raw text of their alignment block; it is parsed the first time the records
+/// are accessed, so header-only operations (filtering by score, size or chromosome)
+/// never pay for it. Eagerly parsed chains behave exactly like a `Vec<AlignmentRecord>`.
+///
+/// A malformed raw block is only detected on first access: [`Alignment::try_records`]
+/// returns the parsing error, while dereferencing yields no records.
+#[derive(Default)]
+pub struct Alignment {
+    raw: Option<Box<[u8]>>,
+    records: OnceLock<std::result::Result<Vec<AlignmentRecord>, ChaintoolsError>>,
+    origin: Origin,
+}
+
+/// Where a raw block was read from, so that its parsing errors point into the file
+#[derive(Debug, Clone, Copy, Default)]
+struct Origin {
+    /// 1-based line number of the first line of the block
+    line: Option<u64>,
+    /// Byte offset of the block
+    offset: Option<u64>,
+    chain_id: Option<ChainId>,
+}
+
+// Records seen through a malformed raw block
+static NO_RECORDS: Vec<AlignmentRecord> = Vec::new();
+
+impl Alignment {
+    /// Create a lazily parsed alignment from the raw text of a chain block.
+    ///
+    /// The block is kept as is and not looked at until the records are accessed.
+    ///
+    /// # Arguments
+    /// * `block` - A byte array containing the alignment of the chain block
+    ///
+    /// # Returns
+    /// * Alignment
     ///
     /// # Example
+    ///
     /// ```
-    /// use chaintools as chain;
+    /// use chaintools::cmap::align::Alignment;
     ///
-    /// let line = b"9\t1\t0\n";
-    /// let data = chain::AlignmentRecord::parse_byte(line);
-    /// println!("{:?}", data);
+    /// let data = Alignment::lazy(b"9\t1\t0\n225\n");
+    /// assert!(!data.is_parsed());
+    /// assert_eq!(data.len(), 2);
+    /// assert!(data.is_parsed());
     ///
-    /// > Ok([AlignmentRecord { size: 9, dt: 1, dq: 0, is_last: false}]);
+    /// let data = Alignment::lazy(b"9\t1\n");
+    /// assert!(data.try_records().is_err());
+    /// assert!(data.is_empty());
     /// ```
-    fn parse_byte(align: &[u8]) -> Result<Vec<AlignmentRecord>> {
-        let mut acc = vec![];
-        let mut align = &align[..];
+    pub fn lazy(block: &[u8]) -> Self {
+        Self {
+            raw: Some(block.into()),
+            records: OnceLock::new(),
+            origin: Origin::default(),
+        }
+    }
 
-        loop {
-            let Some(sep) = memchr(b'\t', align) else {
-                if align.len() < 2 {
-                    break;
-                } // YM: Does this mean that the last block does not get recorded?
+    /// Record where the raw block was read from
+    ///
+    /// Parsing errors of the block are then reported at their line and byte
+    /// offset in the file, and in the given chain.
+    ///
+    /// # Arguments
+    /// * `line` - 1-based line number of the first line of the block, if known
+    /// * `offset` - Byte offset of the block, if known
+    /// * `chain_id` - ID of the chain the block belongs to
+    pub(crate) fn locate(&mut self, line: Option<u64>, offset: Option<u64>, chain_id: ChainId) {
+        self.origin = Origin { line, offset, chain_id: Some(chain_id) };
+    }
 
-                let end = memchr(b'\n', &align[..]).with_context(|| {
-                    format!(
-                        "Failed to find separator in: {:?}. Bad formatted line!",
-                        String::from_utf8_lossy(align)
-                    )
-                })?;
+    /// Check whether the raw alignment block was already parsed, successfully or not.
+    ///
+    /// # Returns
+    /// * bool
+    pub fn is_parsed(&self) -> bool {
+        self.records.get().is_some()
+    }
 
-                let size = from_utf8(&align[..end])
-                    .context("Failed to parse size")
-                    .and_then(|s| s.parse::<u32>().context("Failed to parse size"))?;
+    /// Get the alignment records, parsing them if needed.
+    ///
+    /// The outcome of parsing is kept, so a malformed block fails every time with the same error.
+    ///
+    /// # Returns
+    /// * Result<&Vec<AlignmentRecord>>
+    /// * A [`ChaintoolsError::BadBlock`] error pointing to the offending line of the block
+    pub fn try_records(&self) -> Result<&Vec<AlignmentRecord>> {
+        self.parsed().as_ref().map_err(|e| e.clone().into())
+    }
 
-                acc.push(AlignmentRecord {
-                    size: size,
-                    dt: 0,
-                    dq: 0,
-                    is_last: true
-                });
-                break;
-            };
+    // Private getter of the outcome of parsing the raw block; errors are moved
+    // from the block to the position it was read from
+    fn parsed(&self) -> &std::result::Result<Vec<AlignmentRecord>, ChaintoolsError> {
+        self.records.get_or_init(|| {
+            let Origin { line, offset, chain_id } = self.origin;
+            AlignmentRecord::parse_byte(self.raw.as_deref().unwrap_or_default()).map_err(|e| {
+                let e = e.shifted(line.map(|x| x - 1), offset);
+                match chain_id {
+                    Some(id) => e.with_chain(id),
+                    None => e,
+                }
+            })
+        })
+    }
+}
 
-            let end = memchr(b'\n', &align[sep..]).unwrap();
-            let mid = memchr(b'\t', &align[sep + 1..]).unwrap();
+impl Deref for Alignment {
+    type Target = Vec<AlignmentRecord>;
 
-            let size = from_utf8(&align[..sep])?.parse::<u32>().with_context(|| {
-                format!("Failed to parse size: {:?}", String::from_utf8_lossy(align))
-            })?;
+    fn deref(&self) -> &Self::Target {
+        self.parsed().as_ref().unwrap_or(&NO_RECORDS)
+    }
+}
 
-            let dt = from_utf8(&align[sep + 1..sep + mid + 1])?
-                .parse::<u32>()
-                .with_context(|| {
-                    format!(
-                        "Failed to parse dt from slice: {:?}",
-                        String::from_utf8_lossy(&align[sep + 1..])
-                    )
-                })?;
-            let dq = from_utf8(&align[sep + mid + 2..sep + end])?
-                .parse::<u32>()
-                .with_context(|| {
-                    format!(
-                        "Failed to parse dq: {:?}",
-                        String::from_utf8_lossy(&align[sep + mid + 2..])
-                    )
-                })?;
+impl DerefMut for Alignment {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // records are parsed now, the raw block is not needed anymore; writing
+        // through a malformed block replaces it with no records
+        let records = match self.records.take() {
+            Some(Ok(records)) => records,
+            Some(Err(_)) => Vec::new(),
+            None => AlignmentRecord::parse_byte(self.raw.as_deref().unwrap_or_default()).unwrap_or_default(),
+        };
+        self.raw = None;
+        self.records = OnceLock::from(Ok(records));
+        self.records
+            .get_mut()
+            .and_then(|x| x.as_mut().ok())
+            .expect("Alignment records were just set")
+    }
+}
 
-            acc.push(AlignmentRecord {
-                size: size,
-                dt: dt,
-                dq: dq,
-                is_last: false
-            });
+impl From<Vec<AlignmentRecord>> for Alignment {
+    fn from(records: Vec<AlignmentRecord>) -> Self {
+        Self {
+            raw: None,
+            records: OnceLock::from(Ok(records)),
+            origin: Origin::default(),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Alignment {
+    type Item = &'a AlignmentRecord;
+    type IntoIter = std::slice::Iter<'a, AlignmentRecord>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
 
-            align = &align[sep + end + 1..];
+impl Clone for Alignment {
+    fn clone(&self) -> Self {
+        match self.records.get() {
+            Some(Ok(records)) => Self::from(records.clone()),
+            // malformed blocks fail again on first access of the clone
+            _ => Self {
+                raw: self.raw.clone(),
+                records: OnceLock::new(),
+                origin: self.origin,
+            },
         }
+    }
+}
 
-        Ok(acc)
+impl Debug for Alignment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.try_records() {
+            Ok(records) => records.fmt(f),
+            Err(_) => write!(f, "Alignment(<unparsable {:?}>)", self.raw.as_deref().map(String::from_utf8_lossy)),
+        }
+    }
+}
+
+impl Serialize for Alignment {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.try_records()
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Alignment {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Vec::<AlignmentRecord>::deserialize(deserializer).map(Self::from)
     }
 }
@@ -1,12 +1,19 @@
 use anyhow::{Context, Result};
 use cubiculum::structs::structs::{Coordinates, Interval, Named};
-use memchr::memchr;
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 // use std::mem;
 use std::str::from_utf8;
 
-use crate::cmap::align::AlignmentRecord;
+use crate::cmap::align::{Alignment, AlignmentRecord};
+use crate::error::ChaintoolsError;
+
+/// Identifier of a chain, as given by the last field of its header.
+///
+/// Chain IDs are 64-bit wide throughout the crate (maps, indices, extraction,
+/// binary containers and errors) so that renumbered or merged chain sets never
+/// overflow, and round-trip losslessly through text and binary formats.
+pub type ChainId = u64;
 
 /// A discrete representation of a genomic chain.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,11 +21,12 @@ pub struct Chain {
     pub score: u64,
     pub refs: ChainHead,
     pub query: ChainHead,
-    pub alignment: Vec<AlignmentRecord>,
-    pub id: u32,
+    pub alignment: Alignment,
+    pub id: ChainId,
 }
 
 /// [YM] An enum specifying for which assemblies block coordinates should be extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockSide {
     Ref,
     Query,
@@ -152,7 +160,7 @@ impl Chain {
     /// * `block` - A byte array containing the alignment of the chain block
     ///
     /// # Returns
-    /// * Option<(u32, Self)>
+    /// * Option<(ChainId, Self)>
     /// * None if the block is empty
     ///
     /// # Example
@@ -169,9 +177,9 @@ impl Chain {
     /// query: ChainHead { chr: "chr5", size: 151006098, strand: '-', start: 43257292, end: 43257528 },
     /// alignment: [AlignmentRecord { size: 9, dt: 1, dq: 0 }], id: 1 }));
     /// ```
-    pub fn from(head: &[u8], block: &[u8]) -> Result<(u32, Self)> {
+    pub fn from(head: &[u8], block: &[u8]) -> Result<(ChainId, Self)> {
         let (score, refs, query, id) = Self::head(head)?;
-        let alignment = AlignmentRecord::parse(block);
+        let alignment = parse_block(block, id)?.into();
 
         Ok((
             id,
@@ -185,6 +193,106 @@ impl Chain {
         ))
     }
 
+    /// Create a new Chain object from a chain header and block,
+    /// deferring parsing of the alignment until it is first accessed.
+    ///
+    /// # Arguments
+    /// * `head` - A byte array containing the header of the chain block
+    /// * `block` - A byte array containing the alignment of the chain block
+    ///
+    /// # Returns
+    /// * Result<(ChainId, Self)>
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::cmap::chain::Chain;
+    ///
+    /// let head = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1";
+    /// let block = b"9\t1\t0\n225\n";
+    /// let (id, chain) = Chain::from_lazy(head, block).unwrap();
+    ///
+    /// assert_eq!(id, 1);
+    /// assert!(!chain.alignment.is_parsed());
+    /// assert_eq!(chain.alignment_sum(), 234);
+    /// ```
+    pub fn from_lazy(head: &[u8], block: &[u8]) -> Result<(ChainId, Self)> {
+        let (score, refs, query, id) = Self::head(head)?;
+
+        Ok((
+            id,
+            Self {
+                score,
+                refs,
+                query,
+                alignment: Alignment::lazy(block),
+                id,
+            },
+        ))
+    }
+
+    /// Build a chain from a malformed chain block, fixing recoverable defects.
+    ///
+    /// See [`crate::io::parse::ParseOptions`] for the list of repairs.
+    ///
+    /// # Arguments
+    /// * `head` - A byte array containing the header of the chain block
+    /// * `block` - A byte array containing the alignment of the chain block
+    ///
+    /// # Returns
+    /// * Result<(Option<ChainId>, Self, Vec<String>)> - the chain ID (`None` if missing, in which
+    ///   case the chain gets ID 0), the chain and a note for every kind of fix
+    pub(crate) fn repair(head: &[u8], block: &[u8]) -> Result<(Option<ChainId>, Self, Vec<String>)> {
+        let mut notes: Vec<String> = Vec::new();
+        let fields: Vec<&[u8]> = head
+            .split(|x| x.is_ascii_whitespace())
+            .filter(|x| !x.is_empty())
+            .collect();
+        let mut line: Vec<u8> = fields.join(&b' ');
+        if line != head.trim_ascii() {
+            notes.push(String::from("normalized field separators in the header"));
+        }
+        // chain keyword, score and two heads of five fields, but no ID
+        let id_missing = fields.len() == 12;
+        if id_missing {
+            line.extend_from_slice(b" 0");
+        }
+        let header = ChainHeader::parse(&line)?;
+        let (alignment, block_notes) = AlignmentRecord::repair(block).map_err(|e| e.with_chain(header.id))?;
+        notes.extend(block_notes);
+
+        let id = header.id;
+        let mut chain = Self {
+            score: header.score,
+            refs: header.refs,
+            query: header.query,
+            alignment: alignment.into(),
+            id,
+        };
+        let (r_span, q_span) = chain.alignment.iter().fold((0u64, 0u64), |(r, q), b| {
+            (r + b.size as u64 + b.dt as u64, q + b.size as u64 + b.dq as u64)
+        });
+        for (name, head, span) in [("reference", &mut chain.refs, r_span), ("query", &mut chain.query, q_span)] {
+            if head.start.checked_add(span) == Some(head.end) {
+                continue;
+            }
+            if head.start + span > head.size {
+                return Err(ChaintoolsError::MalformedHeader {
+                    line: None,
+                    offset: None,
+                    chain_id: Some(id),
+                    message: format!(
+                        "Alignment spans {} bases from {} start {}, beyond its size {}",
+                        span, name, head.start, head.size
+                    ),
+                }.into());
+            }
+            notes.push(format!("{} end {} recomputed as {} from the alignment", name, head.end, head.start + span));
+            head.end = head.start + span;
+        }
+        Ok(((!id_missing).then_some(id), chain, notes))
+    }
+
     /// Get the chain object as a string.
     ///
     /// # Arguments
@@ -413,7 +521,7 @@ impl Chain {
     /// * `header` - A byte array containing the header of the chain block
     ///
     /// # Returns
-    /// * (i64, ChainHead, ChainHead, u32)
+    /// * (i64, ChainHead, ChainHead, ChainId)
     ///
     /// # Example
     ///
@@ -427,42 +535,9 @@ impl Chain {
     /// > (4900, ChainHead { chr: "chrY", size: 58368225, strand: '+', start: 25985403, end: 25985638 },
     /// ChainHead { chr: "chr5", size: 151006098, strand: '-', start: 43257292, end: 43257528 }, 1);
     /// ```
-    pub fn head(header: &[u8]) -> Result<(u64, ChainHead, ChainHead, u32)> {
-        let mut acc = vec![];
-        let mut header = &header[..];
-        loop {
-            let Some(sep) = memchr(b' ', header) else {
-                acc.push(header);
-                break;
-            };
-            acc.push(&header[..sep]);
-            header = &header[sep + 1..];
-        }
-
-        let refs = ChainHead::from(&acc[2..7])?;
-        let query = ChainHead::from(&acc[7..12])?;
-
-        let score = from_utf8(&acc[1])
-            .unwrap()
-            .parse::<u64>()
-            .with_context(|| {
-                format!(
-                    "Failed to parse score in: {:?}. Bad formatted line!",
-                    String::from_utf8_lossy(header)
-                )
-            })?;
-
-        let id = from_utf8(&acc.last().unwrap())
-            .unwrap()
-            .parse::<u32>()
-            .with_context(|| {
-                format!(
-                    "Failed to parse id in: {:?}. Bad formatted line!",
-                    String::from_utf8_lossy(header)
-                )
-            })?;
-
-        Ok((score, refs, query, id))
+    pub fn head(header: &[u8]) -> Result<(u64, ChainHead, ChainHead, ChainId)> {
+        let head = ChainHeader::parse(header)?;
+        Ok((head.score, head.refs, head.query, head.id))
     }
 
     /// [YM]
@@ -572,6 +647,48 @@ impl Chain {
         blocks
     }
 
+    /// Walk over the aligned blocks of the chain with their reference and query coordinates
+    ///
+    /// This is the block walker the projection methods share; a malformed lazily
+    /// parsed alignment yields no blocks (see [`Chain::records`]).
+    ///
+    /// # Arguments
+    /// `self` - A Chain object
+    ///
+    /// # Returns
+    /// An iterator of BlockSpan, in alignment order
+    pub(crate) fn walk_blocks(&self) -> impl Iterator<Item = BlockSpan<'_>> {
+        let (mut r_start, mut q_start) = (self.refs.start, self.query.start);
+        self.alignment.iter().enumerate().map(move |(index, record)| {
+            let block = BlockSpan {
+                index,
+                record,
+                r_start,
+                r_end: r_start + record.size as u64,
+                q_start,
+                q_end: q_start + record.size as u64,
+            };
+            r_start = block.r_end + record.dt as u64;
+            q_start = block.q_end + record.dq as u64;
+            block
+        })
+    }
+
+    /// Get the alignment records of the chain, parsing them if needed
+    ///
+    /// # Arguments
+    /// `self` - A Chain object
+    ///
+    /// # Returns
+    /// * Result<&Vec<AlignmentRecord>>
+    /// * A [`ChaintoolsError::BadBlock`] error carrying the chain ID if the alignment block is malformed
+    pub(crate) fn records(&self) -> Result<&Vec<AlignmentRecord>> {
+        self.alignment.try_records().map_err(|e| match e.downcast::<ChaintoolsError>() {
+            Ok(e) => e.with_chain(self.id).into(),
+            Err(e) => e,
+        })
+    }
+
     /// Get the total sum of aligned blocks lengths
     /// 
     /// # Arguments
@@ -589,8 +706,36 @@ impl Chain {
     }
 }
 
+/// An aligned block of a chain, as yielded by [`Chain::walk_blocks`]
+///
+/// Query coordinates run along the query strand of the chain, as in the chain header.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BlockSpan<'a> {
+    /// 0-based index of the block in the alignment
+    pub(crate) index: usize,
+    pub(crate) record: &'a AlignmentRecord,
+    pub(crate) r_start: u64,
+    pub(crate) r_end: u64,
+    pub(crate) q_start: u64,
+    pub(crate) q_end: u64,
+}
+
+impl BlockSpan<'_> {
+    /// Project a reference interval lying within the block to the query
+    ///
+    /// # Arguments
+    /// `start` - 0-based reference start, not before the block start
+    /// `end` - Exclusive reference end, not past the block end
+    ///
+    /// # Returns
+    /// (u64, u64) - the query interval, along the query strand of the chain
+    pub(crate) fn project(&self, start: u64, end: u64) -> (u64, u64) {
+        (self.q_start + start - self.r_start, self.q_start + end - self.r_start)
+    }
+}
+
 /// A ref/query chain head object.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ChainHead {
     pub chr: String,
     pub size: u64,
@@ -620,6 +765,13 @@ impl ChainHead {
     ///
     /// > Ok(ChainHead { chr: "chrY", size: 58368225, strand: '+', start: 25985403, end: 25985638 });
     pub fn from(header: &[&[u8]]) -> Result<Self> {
+        if header.len() < 5 {
+            anyhow::bail!(
+                "Expected 5 fields in chain head, got {}: {:?}",
+                header.len(),
+                header.iter().map(|x| String::from_utf8_lossy(x)).collect::<Vec<_>>()
+            );
+        }
         Ok(Self {
             chr: from_utf8(header[0])
                 .context("Failed to decode chr data as UTF-8")?
@@ -706,4 +858,144 @@ impl ChainHead {
             self.end.to_string(),
         ]
     }
+
+    // Private in-place parser reusing the chromosome name buffer
+    fn update<'a, I>(&mut self, fields: &mut I) -> Result<()>
+    where
+        I: Iterator<Item = &'a [u8]>,
+    {
+        let chr = fields.next().context("Missing chromosome name")?;
+        self.chr.clear();
+        self.chr.push_str(from_utf8(chr).context("Failed to decode chr data as UTF-8")?);
+        self.size = parse_field(fields.next(), "size")?;
+        self.strand = fields
+            .next()
+            .and_then(|x| from_utf8(x).ok())
+            .and_then(|x| x.chars().next())
+            .context("Failed to parse strand")?;
+        self.start = parse_field(fields.next(), "start")?;
+        self.end = parse_field(fields.next(), "end")?;
+        Ok(())
+    }
+}
+
+/// Header fields of a chain, parsed without its alignment block.
+///
+/// Used by [`crate::io::reader::Reader::extract_where`] to select chains before
+/// their bodies are parsed; a single header object is reused for all chains in a file.
+#[derive(Debug, Clone, Default)]
+pub struct ChainHeader {
+    pub score: u64,
+    pub refs: ChainHead,
+    pub query: ChainHead,
+    pub id: ChainId,
+}
+
+impl ChainHeader {
+    /// Create a new chain header object from a chain header line.
+    ///
+    /// # Arguments
+    /// * `header` - A byte array containing the header line of the chain block
+    ///
+    /// # Returns
+    /// * Result<ChainHeader>
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::cmap::chain::ChainHeader;
+    ///
+    /// let head = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1";
+    /// let data = ChainHeader::parse(head).unwrap();
+    ///
+    /// assert_eq!(data.score, 4900);
+    /// assert_eq!(data.query.chr, "chr5");
+    /// assert_eq!(data.id, 1);
+    /// ```
+    pub fn parse(header: &[u8]) -> Result<Self> {
+        let mut head = Self::default();
+        head.update(header)?;
+        Ok(head)
+    }
+
+    /// Parse a chain header line in place, reusing the buffers of the previous header.
+    ///
+    /// # Arguments
+    /// * `header` - A byte array containing the header line of the chain block
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub fn update(&mut self, header: &[u8]) -> Result<()> {
+        self.update_fields(header).map_err(|e| {
+            // report the chain ID if the last field still holds one
+            let fields: Vec<&[u8]> = header
+                .trim_ascii_end()
+                .split(|x| x.is_ascii_whitespace())
+                .filter(|x| !x.is_empty())
+                .collect();
+            let chain_id = match fields.as_slice() {
+                [.., id] if fields.len() == 13 => from_utf8(id).ok().and_then(|x| x.parse::<ChainId>().ok()),
+                _ => None,
+            };
+            ChaintoolsError::MalformedHeader {
+                line: None,
+                offset: None,
+                chain_id,
+                message: format!("{} in {:?}", e, String::from_utf8_lossy(header)),
+            }
+            .into()
+        })
+    }
+
+    // Private field parser for update()
+    fn update_fields(&mut self, header: &[u8]) -> Result<()> {
+        let mut fields = header
+            .trim_ascii_end()
+            .split(|x| x.is_ascii_whitespace())
+            .filter(|x| !x.is_empty());
+        if fields.next() != Some(&b"chain"[..]) {
+            anyhow::bail!("Header does not start with the 'chain' keyword");
+        }
+        self.score = parse_field(fields.next(), "score")?;
+        self.refs.update(&mut fields)?;
+        self.query.update(&mut fields)?;
+        self.id = parse_field(fields.next(), "id")?;
+        if fields.next().is_some() {
+            anyhow::bail!("Unexpected fields after the chain ID");
+        }
+        Ok(())
+    }
+
+    /// Build a chain from the header and the raw alignment block.
+    ///
+    /// # Arguments
+    /// * `block` - A byte array containing the alignment of the chain block
+    ///
+    /// # Returns
+    /// * Result<Chain>
+    pub fn to_chain(&self, block: &[u8]) -> Result<Chain> {
+        Ok(Chain {
+            score: self.score,
+            refs: self.refs.clone(),
+            query: self.query.clone(),
+            alignment: parse_block(block, self.id)?.into(),
+            id: self.id,
+        })
+    }
+}
+
+// Private block parser attaching the chain ID to parsing errors
+fn parse_block(block: &[u8], id: ChainId) -> Result<Vec<AlignmentRecord>> {
+    AlignmentRecord::parse(block).map_err(|e| match e.downcast::<ChaintoolsError>() {
+        Ok(e) => e.with_chain(id).into(),
+        Err(e) => e,
+    })
+}
+
+// Private parser for numeric header fields
+fn parse_field<T: std::str::FromStr>(field: Option<&[u8]>, name: &str) -> Result<T> {
+    field
+        .and_then(|x| from_utf8(x).ok())
+        .and_then(|x| x.parse::<T>().ok())
+        .with_context(|| format!("Failed to parse {}", name))
 }
@@ -1,13 +1,313 @@
-use fxhash::FxHashMap;
+use anyhow::Result;
+use fxhash::{FxHashMap, FxHashSet};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::Deref;
 
-use crate::cmap::chain::Chain;
+use crate::cmap::chain::{Chain, ChainId};
+use crate::error::ChaintoolsError;
+
+/// How colliding chain IDs are resolved when chains are combined
+///
+/// Used by [`ChainMap::merge`], where the left chain is the one already in the map,
+/// and by the chain parsers for IDs repeated within a file (see
+/// [`crate::io::parse::ParseOptions::with_duplicates`]), where it is the first one in file order.
+/// Parsers default to [`MergePolicy::PreferLeft`], as chain files have always been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+    /// Fail on the first colliding ID
+    Error,
+    /// Keep the left chain
+    #[default]
+    PreferLeft,
+    /// Keep the chain with the higher score, the left one on ties
+    PreferHigherScore,
+    /// Keep the left chain and give the others IDs following the largest one
+    Renumber,
+}
+
+/// Orderings available for collections of chains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainOrder {
+    /// Keep chains in the order they were supplied
+    Unsorted,
+    /// Ascending chain ID
+    Id,
+    /// Descending score, ties broken by ascending ID (chainSort semantics)
+    Score,
+    /// Reference chromosome, then start and end coordinates
+    Reference,
+    /// Query chromosome, then start and end coordinates as given in the header
+    Query,
+}
+
+impl ChainOrder {
+    /// Compare two chains according to the ordering
+    ///
+    /// # Arguments
+    /// * `a` - A chain
+    /// * `b` - A chain
+    ///
+    /// # Returns
+    /// * Ordering
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\nchain 20 chr1 100 + 20 30 chr2 100 + 20 30 2\n10\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    /// let (a, b) = (chains.get(&1).unwrap(), chains.get(&2).unwrap());
+    ///
+    /// assert!(ChainOrder::Score.compare(b, a).is_lt());
+    /// assert!(ChainOrder::Reference.compare(a, b).is_lt());
+    /// assert!(ChainOrder::Query.compare(a, b).is_lt());
+    /// ```
+    pub fn compare(&self, a: &Chain, b: &Chain) -> Ordering {
+        match self {
+            ChainOrder::Unsorted => Ordering::Equal,
+            ChainOrder::Id => a.id.cmp(&b.id),
+            ChainOrder::Score => b.score.cmp(&a.score).then(a.id.cmp(&b.id)),
+            ChainOrder::Reference => a
+                .refs
+                .chr
+                .cmp(&b.refs.chr)
+                .then(a.refs.start.cmp(&b.refs.start))
+                .then(a.refs.end.cmp(&b.refs.end))
+                .then(a.id.cmp(&b.id)),
+            ChainOrder::Query => a
+                .query
+                .chr
+                .cmp(&b.query.chr)
+                .then(a.query.start.cmp(&b.query.start))
+                .then(a.query.end.cmp(&b.query.end))
+                .then(a.id.cmp(&b.id)),
+        }
+    }
+}
+
+/// File-level metadata of a chain file, taken from its `#` comment lines
+///
+/// Lines are kept verbatim (without the leading `#`) and in file order, so they are
+/// re-emitted unchanged by [`crate::io::writer::Writer::write_map`]. Each line is also
+/// read as a key/value pair split at the first `:` or `=`; lines without a separator
+/// are keys with an empty value.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Metadata {
+    lines: Vec<String>,
+}
+
+impl Metadata {
+    /// Create empty metadata
+    ///
+    /// # Returns
+    /// * Metadata
+    pub fn new() -> Self {
+        Self { lines: Vec::new() }
+    }
+
+    /// Collect the `#` comment lines of chain data
+    ///
+    /// # Arguments
+    /// * `data` - A byte slice of chain data
+    ///
+    /// # Returns
+    /// * Metadata
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::Metadata;
+    ///
+    /// let data = b"#source: hg38ToMm10\r\n# version=2\nchain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n";
+    /// let meta = Metadata::from_bytes(data);
+    ///
+    /// assert_eq!(meta.get("source"), Some("hg38ToMm10"));
+    /// assert_eq!(meta.get("version"), Some("2"));
+    /// ```
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut meta = Self::new();
+        for line in data.split(|x| *x == b'\n') {
+            if let Some(comment) = line.strip_prefix(b"#") {
+                meta.push(&String::from_utf8_lossy(comment.trim_ascii_end()));
+            }
+        }
+        meta
+    }
+
+    /// Append a comment line, given without its leading `#`
+    ///
+    /// # Arguments
+    /// * `line` - A comment line
+    pub fn push(&mut self, line: &str) {
+        self.lines.push(line.to_string());
+    }
+
+    /// Set the value of a key, replacing the first line holding that key
+    /// or appending a `key: value` line
+    ///
+    /// # Arguments
+    /// * `key` - A metadata key
+    /// * `value` - A metadata value
+    pub fn insert(&mut self, key: &str, value: &str) {
+        let line = format!("{}: {}", key, value);
+        match self.lines.iter().position(|x| Self::split(x).0 == key) {
+            Some(i) => self.lines[i] = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    /// Get the value of the first line holding a key
+    ///
+    /// # Arguments
+    /// * `key` - A metadata key
+    ///
+    /// # Returns
+    /// * Option<&str>
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.iter().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Iterate over the key/value pairs in file order
+    ///
+    /// # Returns
+    /// * An iterator of (&str, &str)
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().map(|x| Self::split(x))
+    }
+
+    /// Iterate over the raw comment lines, without their leading `#`
+    ///
+    /// # Returns
+    /// * An iterator of &str
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|x| x.as_str())
+    }
+
+    /// Get the number of comment lines
+    ///
+    /// # Returns
+    /// * usize
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Check whether there is no metadata
+    ///
+    /// # Returns
+    /// * bool
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // Private splitter of a comment line into a key and a value
+    fn split(line: &str) -> (&str, &str) {
+        match line.find([':', '=']) {
+            Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            None => (line.trim(), ""),
+        }
+    }
+}
+
+/// Source tags of the chains of a ChainMap, e.g. the file every chain was read from
+///
+/// Each distinct tag is stored once; chains without a tag have no entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Provenance {
+    sources: Vec<String>,
+    chains: FxHashMap<ChainId, usize>,
+}
+
+impl Provenance {
+    /// Create an empty provenance record
+    ///
+    /// # Returns
+    /// * Provenance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the source tag of a chain
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    ///
+    /// # Returns
+    /// * Option<&str>
+    pub fn get(&self, id: &ChainId) -> Option<&str> {
+        self.chains.get(id).map(|x| self.sources[*x].as_str())
+    }
+
+    /// Tag a chain with a source
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    /// * `source` - A source tag
+    pub fn set(&mut self, id: ChainId, source: &str) {
+        let i = match self.sources.iter().position(|x| x == source) {
+            Some(i) => i,
+            None => {
+                self.sources.push(source.to_string());
+                self.sources.len() - 1
+            }
+        };
+        self.chains.insert(id, i);
+    }
+
+    /// Remove the source tag of a chain
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    pub fn remove(&mut self, id: &ChainId) {
+        self.chains.remove(id);
+    }
+
+    /// Iterate over the distinct source tags
+    ///
+    /// # Returns
+    /// * Iterator<Item = &str>
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(|x| x.as_str())
+    }
+
+    /// Check whether no chain is tagged
+    ///
+    /// # Returns
+    /// * bool
+    pub fn is_empty(&self) -> bool {
+        self.chains.is_empty()
+    }
+
+    // Private copy of the tags of the given chains only
+    fn subset<'a, I: Iterator<Item = &'a ChainId>>(&self, ids: I) -> Self {
+        if self.is_empty() {
+            return Self::new();
+        }
+        let chains = ids.filter_map(|id| self.chains.get(id).map(|x| (*id, *x))).collect();
+        Self { sources: self.sources.clone(), chains }
+    }
+}
+
+/// How chains of two ChainMaps are matched by [`ChainMap::intersection`] and [`ChainMap::difference`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchBy {
+    /// Chains with the same ID match
+    Id,
+    /// Chains with the same score, heads and alignment match, whatever their IDs
+    Content,
+}
 
 /// A map of chains
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChainMap {
-    pub map: FxHashMap<u32, Chain>,
+    pub map: FxHashMap<ChainId, Chain>,
+    /// File-level metadata from `#` comment lines
+    #[serde(default)]
+    pub metadata: Metadata,
+    /// Optional source tags of the chains, see [`ChainMap::with_source`]
+    #[serde(default)]
+    pub provenance: Provenance,
 }
 
 impl ChainMap {
@@ -25,6 +325,8 @@ impl ChainMap {
     pub fn new() -> Self {
         Self {
             map: FxHashMap::default(),
+            metadata: Metadata::new(),
+            provenance: Provenance::new(),
         }
     }
 
@@ -48,7 +350,7 @@ impl ChainMap {
     /// >>> Some(Chain { score: 0, refs: ChainHead { size: 0, start: 0, end: 0, strand: 0 },
     /// query: ChainHead { size: 0, start: 0, end: 0, strand: 0 }, alinment: [], id: 123 })
     /// ```
-    pub fn get(&self, key: &u32) -> Option<&Chain> {
+    pub fn get(&self, key: &ChainId) -> Option<&Chain> {
         self.map.get(key)
     }
 
@@ -72,7 +374,7 @@ impl ChainMap {
     /// >>> Some(Chain { score: 0, refs: ChainHead { size: 0, start: 0, end: 0, strand: 0 },
     /// query: ChainHead { size: 0, start: 0, end: 0, strand: 0 }, alinment: [], id: 123 })
     /// ```
-    pub fn get_mut(&mut self, key: &u32) -> Option<&mut Chain> {
+    pub fn get_mut(&mut self, key: &ChainId) -> Option<&mut Chain> {
         self.map.get_mut(key)
     }
 
@@ -97,14 +399,14 @@ impl ChainMap {
     where
         F: Fn(&Chain) -> bool,
     {
-        let fmap: FxHashMap<u32, Chain> = self
+        let fmap: FxHashMap<ChainId, Chain> = self
             .map
             .iter()
             .filter(|(_, chain)| fc(chain))
             .map(|(id, chain)| (*id, chain.clone()))
             .collect();
 
-        Self { map: fmap }
+        self.subset(fmap)
     }
 
     /// Insert a chain into a ChainMap
@@ -124,8 +426,9 @@ impl ChainMap {
     /// let chains = Reader::from_file("file.chain").unwrap();
     /// chains.insert(123, Chain{ score: 0, refs: ChainHead { size: 0, start: 0, end: 0, strand: 0 }, ..., id: 123});
     /// ```
-    pub fn insert(&mut self, key: u32, value: Chain) -> &mut ChainMap {
+    pub fn insert(&mut self, key: ChainId, value: Chain) -> &mut ChainMap {
         self.map.insert(key, value);
+        self.provenance.remove(&key);
         self
     }
 
@@ -145,12 +448,13 @@ impl ChainMap {
     /// let chains = Reader::from_file("file.chain").unwrap();
     /// chains.remove(&123);
     /// ```
-    pub fn remove(&mut self, key: &u32) -> &mut ChainMap {
+    pub fn remove(&mut self, key: &ChainId) -> &mut ChainMap {
         self.map.remove(key);
+        self.provenance.remove(key);
         self
     }
 
-    /// Sort the ChainMap by chain ids
+    /// Get the number of chains in the ChainMap
     ///
     /// # Returns
     /// * usize
@@ -172,7 +476,7 @@ impl ChainMap {
     /// Iterate over the ChainMap
     ///
     /// # Returns
-    /// * Iterator<Item = (&u32, &Chain)>
+    /// * Iterator<Item = (&ChainId, &Chain)>
     ///
     /// # Example
     /// ```
@@ -185,14 +489,14 @@ impl ChainMap {
     ///   break;
     /// }
     /// ```
-    pub fn iter(&self) -> impl Iterator<Item = (&u32, &Chain)> {
+    pub fn iter(&self) -> impl Iterator<Item = (&ChainId, &Chain)> {
         self.map.iter()
     }
 
     /// Iterate over mut ChainMap
     ///
     /// # Returns
-    /// * Iterator<Item = (&u32, &mut Chain)>
+    /// * Iterator<Item = (&ChainId, &mut Chain)>
     ///
     /// # Example
     /// ```
@@ -205,14 +509,14 @@ impl ChainMap {
     ///  break;
     /// }
     /// ```
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&u32, &mut Chain)> {
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&ChainId, &mut Chain)> {
         self.map.iter_mut()
     }
 
     /// Iterate over the keys of the ChainMap
     ///
     /// # Returns
-    /// * Iterator<Item = &u32>
+    /// * Iterator<Item = &ChainId>
     ///
     /// # Example
     /// ```
@@ -224,7 +528,7 @@ impl ChainMap {
     ///    println!("{:?}", key);
     /// }
     /// ```
-    pub fn keys(&self) -> impl Iterator<Item = &u32> {
+    pub fn keys(&self) -> impl Iterator<Item = &ChainId> {
         self.map.keys()
     }
 
@@ -247,6 +551,69 @@ impl ChainMap {
         self.map.values()
     }
 
+    /// Iterate over the chains in a given order
+    ///
+    /// Only references to the chains are sorted; chains are never cloned.
+    ///
+    /// # Arguments
+    /// * `order` - The order in which chains are yielded
+    ///
+    /// # Returns
+    /// * Iterator<Item = &Chain>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\nchain 20 chr1 100 + 20 30 chr2 100 + 20 30 2\n10\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    /// let ids: Vec<u64> = chains.sorted(ChainOrder::Score).map(|chain| chain.id).collect();
+    ///
+    /// assert_eq!(ids, vec![2, 1]);
+    /// ```
+    pub fn sorted(&self, order: ChainOrder) -> impl Iterator<Item = &Chain> {
+        let mut chains: Vec<&Chain> = self.map.values().collect();
+        chains.par_sort_unstable_by(|a, b| order.compare(a, b));
+        chains.into_iter()
+    }
+
+    /// Consume the ChainMap into a vector of chains in a given order
+    ///
+    /// # Arguments
+    /// * `order` - The order of the chains
+    ///
+    /// # Returns
+    /// * Vec<Chain>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 50 60 chr2 100 + 0 10 1\n10\n\nchain 20 chr1 100 + 20 30 chr2 100 + 20 30 2\n10\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap().into_sorted_vec(ChainOrder::Reference);
+    ///
+    /// assert_eq!(chains[0].id, 2);
+    /// ```
+    pub fn into_sorted_vec(self, order: ChainOrder) -> Vec<Chain> {
+        let mut chains: Vec<Chain> = self.map.into_values().collect();
+        chains.par_sort_unstable_by(|a, b| order.compare(a, b));
+        chains
+    }
+
+    /// Consume the ChainMap into an owned sorted container, keeping its metadata and source tags
+    ///
+    /// # Arguments
+    /// * `order` - The order of the chains
+    ///
+    /// # Returns
+    /// * SortedChains
+    pub fn into_sorted(self, order: ChainOrder) -> SortedChains {
+        let ChainMap { map, metadata, provenance } = self;
+        SortedChains { metadata, provenance, ..SortedChains::new(map.into_values(), order) }
+    }
+
     /// Iterate over mut values of the ChainMap
     ///
     /// # Returns
@@ -293,7 +660,7 @@ impl ChainMap {
             .filter(|(_, v)| v.score >= score)
             .map(|(k, v)| (*k, v.clone()))
             .collect();
-        Self { map }
+        self.subset(map)
     }
 
     /// Filter the ChainMap by reference size
@@ -320,7 +687,7 @@ impl ChainMap {
             .filter(|(_, v)| v.refs.size >= size)
             .map(|(k, v)| (*k, v.clone()))
             .collect();
-        Self { map }
+        self.subset(map)
     }
 
     /// Filter the ChainMap by query size
@@ -347,7 +714,7 @@ impl ChainMap {
             .filter(|(_, v)| v.query.size >= size)
             .map(|(k, v)| (*k, v.clone()))
             .collect();
-        Self { map }
+        self.subset(map)
     }
 
     /// Filter the ChainMap by chain ids
@@ -369,13 +736,375 @@ impl ChainMap {
     /// println!("{:?}", filt_chains);
     ///
     /// >>> 2
-    pub fn filter_id(&self, ids: Vec<u32>) -> Self {
+    pub fn filter_id(&self, ids: Vec<ChainId>) -> Self {
         let map = self
             .map
             .par_iter()
             .filter(|(k, _)| ids.contains(k))
             .map(|(k, v)| (*k, v.clone()))
             .collect();
-        Self { map }
+        self.subset(map)
+    }
+
+    /// Find chains stored more than once under different IDs
+    ///
+    /// Chains are duplicates if they share the score, both heads and the alignment,
+    /// as happens when chain files from different runs are concatenated and read
+    /// with [`MergePolicy::Renumber`].
+    ///
+    /// # Arguments
+    /// * `self` - A ChainMap object
+    ///
+    /// # Returns
+    /// * Result<Vec<Vec<ChainId>>> - groups of IDs of identical chains, each sorted in ascending
+    ///   order and ordered by their first ID; empty if there are no duplicates. Fails on a
+    ///   lazily read chain whose alignment cannot be parsed
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::MergePolicy;
+    /// use chaintools::io::parse::ParseOptions;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n\
+    /// chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n\
+    /// chain 10 chr1 100 + 0 10 chr2 100 + 0 10 4\n10\n\n";
+    /// let options = ParseOptions::strict().with_duplicates(MergePolicy::Renumber);
+    /// let (chains, _) = Reader::from_bytes_with(data, &options).unwrap();
+    ///
+    /// assert_eq!(chains.duplicates().unwrap(), vec![vec![1, 4, 5]]);
+    /// ```
+    pub fn duplicates(&self) -> Result<Vec<Vec<ChainId>>> {
+        let keys: Vec<(ChainId, ContentKey)> = self
+            .map
+            .par_iter()
+            .map(|(id, chain)| Ok((*id, content_key(chain)?)))
+            .collect::<Result<_>>()?;
+        let mut groups: FxHashMap<ContentKey, Vec<ChainId>> = FxHashMap::default();
+        for (id, key) in keys {
+            groups.entry(key).or_default().push(id);
+        }
+        let mut duplicates: Vec<Vec<ChainId>> = groups
+            .into_values()
+            .filter(|x| x.len() > 1)
+            .map(|mut x| {
+                x.sort_unstable();
+                x
+            })
+            .collect();
+        duplicates.sort_unstable();
+        Ok(duplicates)
+    }
+
+    /// Tag all chains with a source, e.g. the file they were read from
+    ///
+    /// # Arguments
+    /// * `source` - A source tag
+    ///
+    /// # Returns
+    /// * ChainMap
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let chains = Reader::from_file("/path/to/run1.chain").unwrap().with_source("run1");
+    /// assert_eq!(chains.source(&1), Some("run1"));
+    /// ```
+    pub fn with_source(mut self, source: &str) -> Self {
+        for id in self.map.keys() {
+            self.provenance.set(*id, source);
+        }
+        self
+    }
+
+    /// Get the source tag of a chain
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    ///
+    /// # Returns
+    /// * Option<&str> - `None` if the chain is missing or untagged
+    pub fn source(&self, id: &ChainId) -> Option<&str> {
+        self.provenance.get(id)
+    }
+
+    /// Move all chains of another ChainMap into this one
+    ///
+    /// Chains keep their source tags and the metadata lines of `other` missing from
+    /// this map are appended. Colliding IDs are resolved according to `policy`:
+    /// * [`MergePolicy::Error`] fails before anything is moved
+    /// * [`MergePolicy::PreferLeft`] keeps the chain of this map
+    /// * [`MergePolicy::PreferHigherScore`] keeps the chain with the higher score,
+    ///   the one of this map on ties
+    /// * [`MergePolicy::Renumber`] gives chains of `other` IDs following the largest
+    ///   ID of both maps, in ascending order of their original IDs
+    ///
+    /// # Arguments
+    /// * `other` - A ChainMap to merge into this one
+    /// * `policy` - How colliding IDs are resolved
+    ///
+    /// # Returns
+    /// * Result<Vec<(ChainId, ChainId)>> - the original and new IDs of renumbered chains of `other`
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::MergePolicy;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let run1 = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n";
+    /// let run2 = b"chain 20 chr1 100 + 50 60 chr2 100 + 50 60 1\n10\n\n";
+    /// let mut chains = Reader::from_bytes(run1).unwrap().with_source("run1");
+    /// let other = Reader::from_bytes(run2).unwrap().with_source("run2");
+    ///
+    /// let renumbered = chains.merge(other, MergePolicy::Renumber).unwrap();
+    /// assert_eq!(renumbered, vec![(1, 2)]);
+    /// assert_eq!(chains.source(&2), Some("run2"));
+    /// ```
+    pub fn merge(&mut self, other: ChainMap, policy: MergePolicy) -> Result<Vec<(ChainId, ChainId)>> {
+        let ChainMap { map, metadata, provenance } = other;
+        if policy == MergePolicy::Error {
+            if let Some(id) = map.keys().filter(|x| self.map.contains_key(x)).min() {
+                return Err(ChaintoolsError::DuplicateChain { chain_id: *id, line: None, offset: None }.into());
+            }
+        }
+        let mut next_id: ChainId = self
+            .map
+            .keys()
+            .chain(map.keys())
+            .max()
+            .map_or(1, |x| x.saturating_add(1));
+
+        let mut chains: Vec<(ChainId, Chain)> = map.into_iter().collect();
+        chains.sort_unstable_by_key(|(id, _)| *id);
+        let mut renumbered: Vec<(ChainId, ChainId)> = Vec::new();
+        for (old_id, mut chain) in chains {
+            let id = match self.map.get(&old_id) {
+                None => old_id,
+                Some(first) => match policy {
+                    MergePolicy::PreferHigherScore if chain.score > first.score => old_id,
+                    MergePolicy::Renumber => {
+                        let new_id = next_id;
+                        next_id = next_id.saturating_add(1);
+                        chain.id = new_id;
+                        renumbered.push((old_id, new_id));
+                        new_id
+                    }
+                    _ => continue,
+                },
+            };
+            self.map.insert(id, chain);
+            match provenance.get(&old_id) {
+                Some(source) => self.provenance.set(id, source),
+                None => self.provenance.remove(&id),
+            }
+        }
+        for line in metadata.lines() {
+            if !self.metadata.lines().any(|x| x == line) {
+                self.metadata.push(line);
+            }
+        }
+        Ok(renumbered)
+    }
+
+    /// Keep the chains matching a chain of another ChainMap
+    ///
+    /// # Arguments
+    /// * `other` - A ChainMap
+    /// * `by` - How chains are matched
+    ///
+    /// # Returns
+    /// * Result<ChainMap> - chains of this map with a match in `other`, under their IDs in this map;
+    ///   matching by content fails on a lazily read chain whose alignment cannot be parsed
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::MatchBy;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let a = Reader::from_bytes(b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n").unwrap();
+    /// let b = Reader::from_bytes(b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 7\n10\n\n").unwrap();
+    ///
+    /// assert_eq!(a.intersection(&b, MatchBy::Id).unwrap().len(), 0);
+    /// assert_eq!(a.intersection(&b, MatchBy::Content).unwrap().len(), 1);
+    /// ```
+    pub fn intersection(&self, other: &ChainMap, by: MatchBy) -> Result<Self> {
+        self.matching(other, by, true)
+    }
+
+    /// Keep the chains not matching any chain of another ChainMap
+    ///
+    /// # Arguments
+    /// * `other` - A ChainMap
+    /// * `by` - How chains are matched
+    ///
+    /// # Returns
+    /// * Result<ChainMap> - chains of this map without a match in `other`; matching by content
+    ///   fails on a lazily read chain whose alignment cannot be parsed
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::MatchBy;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let a = Reader::from_bytes(b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n").unwrap();
+    /// let b = Reader::from_bytes(b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 7\n10\n\n").unwrap();
+    ///
+    /// assert_eq!(a.difference(&b, MatchBy::Id).unwrap().len(), 1);
+    /// assert_eq!(a.difference(&b, MatchBy::Content).unwrap().len(), 0);
+    /// ```
+    pub fn difference(&self, other: &ChainMap, by: MatchBy) -> Result<Self> {
+        self.matching(other, by, false)
+    }
+
+    // Private filter for intersection() and difference()
+    fn matching(&self, other: &ChainMap, by: MatchBy, keep: bool) -> Result<Self> {
+        let map = match by {
+            MatchBy::Id => self
+                .map
+                .par_iter()
+                .filter(|(k, _)| other.map.contains_key(k) == keep)
+                .map(|(k, v)| (*k, v.clone()))
+                .collect(),
+            MatchBy::Content => {
+                let keys: FxHashSet<ContentKey> = other.map.par_iter().map(|(_, v)| content_key(v)).collect::<Result<_>>()?;
+                self.map
+                    .par_iter()
+                    .map(|(k, v)| Ok((k, v, keys.contains(&content_key(v)?))))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|(_, _, found)| *found == keep)
+                    .map(|(k, v, _)| (*k, v.clone()))
+                    .collect()
+            }
+        };
+        Ok(self.subset(map))
+    }
+
+    // Private constructor of a ChainMap holding some of the chains of this one
+    fn subset(&self, map: FxHashMap<ChainId, Chain>) -> Self {
+        let provenance = self.provenance.subset(map.keys());
+        Self { map, metadata: self.metadata.clone(), provenance }
+    }
+}
+
+/// Score, heads and alignment of a chain, identifying it regardless of its ID
+type ContentKey = (u64, String, String, String);
+
+// Private content key of a chain; malformed lazily parsed alignments fail here
+// instead of comparing as empty
+fn content_key(chain: &Chain) -> Result<ContentKey> {
+    chain.records()?;
+    Ok((chain.score, chain.refs.to_string(), chain.query.to_string(), chain.alignment()))
+}
+
+/// An owned collection of chains kept in a fixed order
+///
+/// Dereferences to a slice of chains, so it can be indexed, iterated over and
+/// handed to [`crate::io::writer::Writer`] functions with [`ChainOrder::Unsorted`]
+/// to write chains in the stored order.
+///
+/// # Example
+/// ```
+/// use chaintools::cmap::map::{ChainOrder, SortedChains};
+/// use chaintools::io::reader::Reader;
+///
+/// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 60 70 1\n10\n\nchain 20 chr1 100 + 20 30 chr2 100 + 20 30 2\n10\n\n";
+/// let sorted: SortedChains = Reader::from_bytes(data).unwrap().with_source("run1").into_sorted(ChainOrder::Query);
+///
+/// assert_eq!(sorted.order(), ChainOrder::Query);
+/// assert_eq!(sorted[0].id, 2);
+/// assert_eq!(sorted.provenance.get(&1), Some("run1"));
+///
+/// let chains = sorted.into_map();
+/// assert_eq!(chains.len(), 2);
+/// assert_eq!(chains.source(&2), Some("run1"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SortedChains {
+    chains: Vec<Chain>,
+    order: ChainOrder,
+    /// File-level metadata of the ChainMap the chains come from
+    pub metadata: Metadata,
+    /// Source tags of the chains, by chain ID
+    pub provenance: Provenance,
+}
+
+impl SortedChains {
+    /// Sort a collection of chains
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains
+    /// * `order` - The order of the chains
+    ///
+    /// # Returns
+    /// * SortedChains
+    pub fn new<I: IntoIterator<Item = Chain>>(chains: I, order: ChainOrder) -> Self {
+        let mut chains: Vec<Chain> = chains.into_iter().collect();
+        chains.par_sort_unstable_by(|a, b| order.compare(a, b));
+        Self { chains, order, metadata: Metadata::new(), provenance: Provenance::new() }
+    }
+
+    /// Get the order of the chains
+    ///
+    /// # Returns
+    /// * ChainOrder
+    pub fn order(&self) -> ChainOrder {
+        self.order
+    }
+
+    /// Add a chain at its place in the order
+    ///
+    /// # Arguments
+    /// * `chain` - A chain object
+    pub fn insert(&mut self, chain: Chain) {
+        let i = self.chains.partition_point(|x| self.order.compare(x, &chain).is_le());
+        self.chains.insert(i, chain);
+    }
+
+    /// Consume the container into the sorted vector of chains
+    ///
+    /// # Returns
+    /// * Vec<Chain>
+    pub fn into_vec(self) -> Vec<Chain> {
+        self.chains
+    }
+
+    /// Consume the container into a ChainMap keyed by chain ID, keeping metadata and source tags
+    ///
+    /// # Returns
+    /// * ChainMap
+    pub fn into_map(self) -> ChainMap {
+        ChainMap {
+            map: self.chains.into_iter().map(|chain| (chain.id, chain)).collect(),
+            metadata: self.metadata,
+            provenance: self.provenance,
+        }
+    }
+}
+
+impl Deref for SortedChains {
+    type Target = [Chain];
+
+    fn deref(&self) -> &Self::Target {
+        &self.chains
+    }
+}
+
+impl IntoIterator for SortedChains {
+    type Item = Chain;
+    type IntoIter = std::vec::IntoIter<Chain>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chains.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a SortedChains {
+    type Item = &'a Chain;
+    type IntoIter = std::slice::Iter<'a, Chain>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chains.iter()
     }
 }
@@ -245,6 +245,10 @@ impl crate::cmap::chain::Chain {
             // the same routine as above
             // first, sort the input vector
             let mut output: FxHashMap<&str, u64> = FxHashMap::default();
+            if intervals.is_empty() {
+                return Ok(output);
+            }
+            Self::check_intervals(intervals)?;
             intervals.sort_by(
                 |a, b| if a.start().unwrap() == b.start().unwrap() {
                     a.end().unwrap().cmp(&b.end().unwrap())
@@ -373,6 +377,10 @@ impl crate::cmap::chain::Chain {
 
         // let output: FxHashMap<&str, (u64, u64)> = FxHashMap::default();
         let mut output: FxHashMap<&str, Interval> = FxHashMap::default();
+        if intervals.is_empty() {
+            return Ok(output);
+        }
+        Self::check_intervals(intervals)?;
 
         intervals.sort_by(
         |a, b| if a.start().unwrap() == b.start().unwrap() {
@@ -854,6 +862,31 @@ impl crate::cmap::chain::Chain {
         max(0, min(coord, self.query.size))
     } 
 
+    /// Checks that every interval has defined coordinates and a name, so that
+    /// the interval-based methods can safely unwrap them
+    /// 
+    /// # Arguments
+    /// `intervals` - A collection of interval objects
+    /// 
+    /// # Returns
+    /// 
+    /// Result<()>, failing with the index of the first incomplete interval
+    /// 
+    fn check_intervals<T>(intervals: &[T]) -> Result<()>
+    where
+        T: Coordinates + Named
+    {
+        for (i, inter) in intervals.iter().enumerate() {
+            if inter.start().is_none() || inter.end().is_none() || inter.name().is_none() {
+                anyhow::bail!(
+                    "Interval {} has undefined coordinates or name: every interval needs a start, an end and a name",
+                    i
+                );
+            }
+        }
+        Ok(())
+    }
+
     /// NOTE: Functions with a trailing underscore implement 'real-time' chain body parsing;
     /// they are currently faster than their `yield_blocks()`-based counterparts,
     /// which we aim to change in futute
@@ -874,7 +907,9 @@ impl crate::cmap::chain::Chain {
     /// `ignore_undefined` - Boolean flag indicating whether projections for intervals fully enclosed in aligned chain gaps should be left undefined
     /// 
     /// # Returns
-    /// Result<&str, Interval> where each interval contains projected coordinates for each input interval 
+    /// Result<&str, Interval> where each interval contains projected coordinates for each input interval;
+    /// intervals that cannot be projected are left out, see [`crate::liftover::Liftover::try_lift`]
+    /// for the reason a record fails to map across a whole chain file
     pub fn map_through_<'a, T>(
         &'a self, 
         // intervals: &mut Vec<(&str, u64, u64, &str)>,
@@ -889,6 +924,10 @@ impl crate::cmap::chain::Chain {
     {
         // let output: FxHashMap<&str, (u64, u64)> = FxHashMap::default();
         let mut output: FxHashMap<&str, Interval> = FxHashMap::default();
+        if intervals.is_empty() {
+            return Ok(output);
+        }
+        Self::check_intervals(intervals)?;
 
         // TODO: Adjust for negative ref strand
 
@@ -930,13 +969,8 @@ impl crate::cmap::chain::Chain {
         // initialize the variables standing for block coordinates
         // (see TODO tho)
         // 
-        let mut r_start: u64 = self.refs.start;
         let r_end: u64 = self.refs.end;
         let q_strand: bool = self.query.strand == '+';
-        let mut q_start: u64 = match q_strand {
-            true => self.query.start,
-            false => self.query.size - self.query.start
-        };
         let query_end  = match q_strand {
             true => self.query.end,
             false => self.query.size - self.query.start
@@ -949,16 +983,19 @@ impl crate::cmap::chain::Chain {
         let mut end_p: u64;
 
         // all set
-        // now, iterate over alignment records
-        // TODO: Implement to_blocks() as yielder to avoid code repetition
-        'outer: for (h, b) in self.alignment.iter().enumerate() {
+        // now, iterate over alignment blocks; malformed lazily parsed blocks fail here
+        self.records()?;
+        'outer: for block in self.walk_blocks() {
+            let (h, b) = (block.index, block.record);
+            // block start coordinates; these are moved to the following gap further down,
+            // and query coordinates run backwards on the negative strand
+            let mut r_start: u64 = block.r_start;
+            let mut q_start: u64 = if q_strand {block.q_start} else {self.query.size - block.q_start};
             // break if the iterator has passed beyond the last interval
             if r_start > max_end {break};
-            let mut r_block_end: u64 = r_start + (b.size as u64);
+            let mut r_block_end: u64 = block.r_end;
             // skip the block preceding the first interval's start in the reference
             if r_block_end + (b.dt as u64) < min_start {
-                r_start += (b.size + b.dt) as u64;
-                q_start = if q_strand {q_start + (b.size + b.dq) as u64} else {q_start - (b.size + b.dq) as u64};
                 continue
             };
 
@@ -1295,8 +1332,6 @@ impl crate::cmap::chain::Chain {
 
             // ignore blocks standing for full deletions in the reference
             if b.dt == 0 {
-                r_start += b.dt as u64;
-                q_start = if q_strand {q_start + b.dq as u64} else {q_start - b.dq as u64};
                 continue
             }
 
@@ -1571,10 +1606,6 @@ impl crate::cmap::chain::Chain {
             min_start = *intervals[curr].start().with_context(||
                 {format!("Interval {} has an undefined start coordinate which cannot be mapped", curr)}
             )?;
-
-            // proceed to the next line
-            r_start += b.dt as u64;
-            q_start = if q_strand {q_start + b.dq as u64} else {q_start - b.dq as u64};
         }
         Ok(output)
     }
@@ -1601,7 +1632,10 @@ impl crate::cmap::chain::Chain {
         // the same routine as above
         // first, sort the input vector
         let mut output: FxHashMap<&str, u64> = FxHashMap::default();
-        assert!(intervals.len() > 0);
+        if intervals.is_empty() {
+            return Ok(output);
+        }
+        Self::check_intervals(intervals)?;
 
         // get the reference strand
         let ref_strand = self.refs.strand == '+';
@@ -1678,7 +1712,7 @@ impl crate::cmap::chain::Chain {
         let mut r_block_end = 0;
 
         // now go
-        for (h, b) in self.alignment.iter().enumerate() {
+        for (h, b) in self.records()?.iter().enumerate() {
             (r_block_start, r_block_end) = match ref_strand {
                 true => {(r_start, r_start + b.size as u64)},
                 false => {(r_start - b.size as u64, r_start)}
@@ -1767,4 +1801,146 @@ impl crate::cmap::chain::Chain {
         }
         Ok(output)
     }
-}
\ No newline at end of file
+
+    /// Projects a BED12 record through the chain, block by block
+    /// 
+    /// Every block is mapped to the query span between its first and last aligned bases,
+    /// as long as its aligned bases make up at least `min_match` of both the block and
+    /// that span; blocks largely enclosed in a chain gap, or stretched over a query insertion,
+    /// are left unmapped instead. thickStart/thickEnd are mapped to the first and last aligned
+    /// bases of the coding region;
+    /// a coding region left without aligned bases makes the projection non-coding.
+    /// The projection is reported on the positive strand of the query: for chains on the
+    /// negative query strand, the strand of the record is flipped and its blocks are
+    /// reordered by query coordinate. Blocks enclosed in chain gaps, lying outside the chain or
+    /// falling short of `min_match` cannot be mapped; they are left out of the projection and
+    /// listed instead
+    /// 
+    /// # Arguments
+    /// `entry` - A BedEntry object in BED12 format, on the reference chromosome of the chain
+    /// `min_match` - Minimum ratio of aligned bases for a block to be mapped, between 0 and 1
+    /// 
+    /// # Returns
+    /// Result<Bed12Projection>, failing if the entry is not a complete BED12 record
+    /// 
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::chain::Chain;
+    /// use cubiculum::structs::structs::BedEntry;
+    /// 
+    /// let head = b"chain 100 chr1 1000 + 100 300 chrA 5000 - 1000 1210 1\n";
+    /// let (_, chain) = Chain::from(head, b"50\t10\t20\n140\n").unwrap();
+    /// let entry = BedEntry::bed12(
+    ///     "chr1".to_string(), 110, 250, "tx1".to_string(), "0".to_string(), true,
+    ///     120, 240, "0".to_string(), 3, vec![30, 10, 50], vec![0, 40, 90]
+    /// );
+    /// let projection = chain.project_bed12(&entry, 0.95).unwrap();
+    /// 
+    /// assert_eq!(projection.unmapped, vec![1]);
+    /// let entry = projection.entry.unwrap();
+    /// assert_eq!((entry.thin_start(), entry.thin_end(), entry.strand()), (Some(3840), Some(3990), Some(false)));
+    /// assert_eq!(entry.exon_starts(), Some(&vec![0, 120]));
+    /// 
+    /// // 20 of 30 bases are aligned, over a query span of 40 bases
+    /// let entry = BedEntry::bed12(
+    ///     "chr1".to_string(), 140, 170, "tx2".to_string(), "0".to_string(), true,
+    ///     140, 170, "0".to_string(), 1, vec![30], vec![0]
+    /// );
+    /// assert_eq!(chain.project_bed12(&entry, 0.95).unwrap().unmapped, vec![0]);
+    /// let entry = chain.project_bed12(&entry, 0.5).unwrap().entry.unwrap();
+    /// assert_eq!((entry.thin_start(), entry.thin_end()), (Some(3920), Some(3960)));
+    /// ```
+    pub fn project_bed12(&self, entry: &BedEntry, min_match: f64) -> Result<Bed12Projection> {
+        if entry.format() != 12 {
+            anyhow::bail!("Cannot project a BED{} entry block by block: a BED12 entry is expected", entry.format());
+        }
+        // malformed lazily parsed alignments fail here instead of leaving every block unmapped
+        self.records()?;
+        let start: u64 = entry.thin_start().with_context(|| "Cannot project a BED12 entry with undefined thinStart")?;
+        let sizes = entry.exon_sizes().with_context(|| "Cannot project a BED12 entry with undefined blockSizes")?;
+        let starts = entry.exon_starts().with_context(|| "Cannot project a BED12 entry with undefined blockStarts")?;
+        if sizes.len() != starts.len() {
+            anyhow::bail!("Cannot project a BED12 entry with {} block sizes and {} block starts", sizes.len(), starts.len());
+        }
+
+        let mut blocks: Vec<(u64, u64)> = Vec::with_capacity(sizes.len());
+        let mut unmapped: Vec<usize> = Vec::new();
+        for (i, (offset, size)) in starts.iter().zip(sizes).enumerate() {
+            match self.project_span(start + offset, start + offset + size) {
+                Some(((lo, hi), aligned)) if aligned as f64 >= max(*size, hi - lo) as f64 * min_match => {
+                    blocks.push((lo, hi))
+                },
+                _ => unmapped.push(i)
+            }
+        }
+        if blocks.is_empty() {
+            return Ok(Bed12Projection { entry: None, unmapped });
+        }
+        // blocks of chains on the negative query strand come in reverse order
+        blocks.sort_unstable();
+        let q_start: u64 = blocks[0].0;
+        let q_end: u64 = blocks.iter().map(|x| x.1).max().unwrap_or(q_start);
+
+        let (thick_start, thick_end) = match (entry.thick_start(), entry.thick_end()) {
+            (Some(ts), Some(te)) if ts < te => match self.project_span(ts, te) {
+                Some(((ts, te), _)) if max(ts, q_start) < min(te, q_end) => (max(ts, q_start), min(te, q_end)),
+                _ => (q_start, q_start)
+            },
+            _ => (q_start, q_start)
+        };
+        let strand: bool = entry.strand().unwrap_or(true) == (self.query.strand == '+');
+        let projected = BedEntry::bed12(
+            self.query.chr.clone(),
+            q_start,
+            q_end,
+            entry.name().cloned().unwrap_or_default(),
+            entry.score().cloned().unwrap_or_else(|| "0".to_string()),
+            strand,
+            thick_start,
+            thick_end,
+            entry.rgb().cloned().unwrap_or_else(|| "0".to_string()),
+            blocks.len() as u16,
+            blocks.iter().map(|(s, e)| e - s).collect(),
+            blocks.iter().map(|(s, _)| s - q_start).collect()
+        );
+        Ok(Bed12Projection { entry: Some(projected), unmapped })
+    }
+
+    /// Projects a reference interval to the query span between its first and last
+    /// aligned bases, on the positive strand of the query
+    /// 
+    /// # Arguments
+    /// `start` - 0-based start of the interval
+    /// `end` - Exclusive end of the interval
+    /// 
+    /// # Returns
+    /// Option<((u64, u64), u64)>, the query span and the number of aligned bases in the
+    /// interval; None if no aligned base of the chain falls into the interval
+    fn project_span(&self, start: u64, end: u64) -> Option<((u64, u64), u64)> {
+        let mut span: Option<(u64, u64)> = None;
+        let mut aligned: u64 = 0;
+        for block in self.walk_blocks() {
+            if block.r_start >= end {break};
+            let (lo, hi) = (max(start, block.r_start), min(end, block.r_end));
+            if lo < hi {
+                let (q_lo, q_hi) = block.project(lo, hi);
+                span = Some(span.map_or((q_lo, q_hi), |(s, _)| (s, q_hi)));
+                aligned += hi - lo;
+            }
+        }
+        let (q_lo, q_hi) = span?;
+        match self.query.strand {
+            '-' => Some(((self.query.size - q_hi, self.query.size - q_lo), aligned)),
+            _ => Some(((q_lo, q_hi), aligned))
+        }
+    }
+}
+
+/// Outcome of projecting a BED12 record through a chain with [`crate::cmap::chain::Chain::project_bed12`]
+#[derive(Debug, Clone)]
+pub struct Bed12Projection {
+    /// The projected BED12 record, in query coordinates; None if no block could be mapped
+    pub entry: Option<BedEntry>,
+    /// 0-based indices of the input blocks that could not be mapped, in input order
+    pub unmapped: Vec<usize>,
+}
@@ -0,0 +1,193 @@
+use fxhash::FxHashMap;
+use rayon::prelude::*;
+use std::fmt::{self, Display};
+
+use crate::cmap::chain::{BlockSide, Chain, ChainHead, ChainId};
+use crate::cmap::map::ChainMap;
+
+/// An internal inconsistency found in a chain
+///
+/// `side` is either [`BlockSide::Ref`] or [`BlockSide::Query`]; blocks are
+/// numbered from 0 in alignment order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationIssue {
+    /// The header span (`end - start`) differs from the sum of `size + dt` (or `size + dq`)
+    SpanMismatch { side: BlockSide, header: u64, alignment: u64 },
+    /// `start` is past `end`, or `end` is past the sequence size
+    OutOfBounds { side: BlockSide, start: u64, end: u64, size: u64 },
+    /// The strand is neither '+' nor '-'
+    InvalidStrand { side: BlockSide, strand: char },
+    /// The final block is followed by a gap
+    TrailingGap { dt: u32, dq: u32 },
+    /// A block has size 0
+    ZeroSizeBlock { block: usize },
+    /// The lazily parsed alignment block cannot be parsed; the alignment is not checked further
+    MalformedAlignment { message: String },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |side: &BlockSide| match side {
+            BlockSide::Query => "query",
+            _ => "reference",
+        };
+        match self {
+            Self::SpanMismatch { side, header, alignment } => write!(
+                f,
+                "{} span is {} in the header but {} in the alignment",
+                name(side), header, alignment
+            ),
+            Self::OutOfBounds { side, start, end, size } => write!(
+                f,
+                "{} coordinates {}-{} are outside of the sequence of size {}",
+                name(side), start, end, size
+            ),
+            Self::InvalidStrand { side, strand } => {
+                write!(f, "{} strand {:?} is neither '+' nor '-'", name(side), strand)
+            }
+            Self::TrailingGap { dt, dq } => {
+                write!(f, "final block is followed by a gap ({}, {})", dt, dq)
+            }
+            Self::ZeroSizeBlock { block } => write!(f, "block {} has size 0", block),
+            Self::MalformedAlignment { message } => write!(f, "malformed alignment: {}", message),
+        }
+    }
+}
+
+impl Chain {
+    /// Check that the chain is internally consistent
+    ///
+    /// The header spans must match the alignment on both sides, coordinates
+    /// must lie within the sequence sizes, strands must be '+' or '-', the final
+    /// block must not be followed by a gap and no block may have size 0. A lazily
+    /// read alignment that fails to parse is reported as such, and only the heads
+    /// are checked then.
+    ///
+    /// # Arguments
+    /// * `self` - A chain object
+    ///
+    /// # Returns
+    /// * Vec<ValidationIssue> - empty if the chain is valid
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::validate::ValidationIssue;
+    /// use chaintools::cmap::chain::BlockSide;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 1000 chr1 250 + 100 300 chrA 5000 + 1000 1210 2\n50\t10\t20\n140\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    /// let issues = chains.get(&2).unwrap().validate();
+    ///
+    /// assert_eq!(
+    ///     issues,
+    ///     vec![ValidationIssue::OutOfBounds { side: BlockSide::Ref, start: 100, end: 300, size: 250 }]
+    /// );
+    ///
+    /// let data = b"chain 1000 chr1 1000 + 100 300 chrA 5000 + 1000 1210 2\n50\tx\t20\n140\n\n";
+    /// let chains = Reader::parse_lazy(data).unwrap();
+    /// let issues = chains.get(&2).unwrap().validate();
+    ///
+    /// assert_eq!(issues.len(), 1);
+    /// assert!(matches!(issues[0], ValidationIssue::MalformedAlignment { .. }));
+    /// ```
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues: Vec<ValidationIssue> = Vec::new();
+        let records = match self.alignment.try_records() {
+            Ok(records) => records,
+            Err(e) => {
+                issues.push(ValidationIssue::MalformedAlignment { message: e.to_string() });
+                for (side, head) in [(BlockSide::Ref, &self.refs), (BlockSide::Query, &self.query)] {
+                    Self::validate_head(side, head, None, &mut issues);
+                }
+                return issues;
+            }
+        };
+        let (mut r_span, mut q_span) = (0u64, 0u64);
+        for (i, block) in records.iter().enumerate() {
+            if block.size == 0 {
+                issues.push(ValidationIssue::ZeroSizeBlock { block: i });
+            }
+            r_span += block.size as u64 + block.dt as u64;
+            q_span += block.size as u64 + block.dq as u64;
+        }
+        if let Some(last) = records.last() {
+            if last.dt != 0 || last.dq != 0 {
+                issues.push(ValidationIssue::TrailingGap { dt: last.dt, dq: last.dq });
+            }
+        }
+        for (side, head, span) in [(BlockSide::Ref, &self.refs, r_span), (BlockSide::Query, &self.query, q_span)] {
+            Self::validate_head(side, head, Some(span), &mut issues);
+        }
+        issues
+    }
+
+    /// Check whether the chain is internally consistent
+    ///
+    /// # Arguments
+    /// * `self` - A chain object
+    ///
+    /// # Returns
+    /// * bool
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_empty()
+    }
+
+    // Private checks of a chain head against the alignment span on its side, if known
+    fn validate_head(side: BlockSide, head: &ChainHead, span: Option<u64>, issues: &mut Vec<ValidationIssue>) {
+        if head.strand != '+' && head.strand != '-' {
+            issues.push(ValidationIssue::InvalidStrand { side, strand: head.strand });
+        }
+        if head.start > head.end || head.end > head.size {
+            issues.push(ValidationIssue::OutOfBounds {
+                side,
+                start: head.start,
+                end: head.end,
+                size: head.size,
+            });
+        }
+        let header = head.end.saturating_sub(head.start);
+        match span {
+            Some(span) if span != header => {
+                issues.push(ValidationIssue::SpanMismatch { side, header, alignment: span });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ChainMap {
+    /// Check that all chains are internally consistent
+    ///
+    /// See [`Chain::validate`] for the checks performed.
+    ///
+    /// # Arguments
+    /// * `self` - A ChainMap object
+    ///
+    /// # Returns
+    /// * FxHashMap<ChainId, Vec<ValidationIssue>> - issues of every invalid chain, by chain ID;
+    ///   empty if all chains are valid
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\nchain 20 chr1 100 + 20 30 chr2 100 + 20 35 2\n10\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    /// let findings = chains.validate();
+    ///
+    /// assert_eq!(findings.len(), 1);
+    /// for issue in &findings[&2] {
+    ///     println!("chain 2: {}", issue);
+    /// }
+    /// ```
+    pub fn validate(&self) -> FxHashMap<ChainId, Vec<ValidationIssue>> {
+        self.map
+            .par_iter()
+            .filter_map(|(id, chain)| {
+                let issues = chain.validate();
+                (!issues.is_empty()).then_some((*id, issues))
+            })
+            .collect()
+    }
+}
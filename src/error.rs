@@ -0,0 +1,242 @@
+/*!
+Contains the error type shared by the library.
+
+Functions keep returning [`anyhow::Result`], but every parsing, lookup and I/O
+failure originates from a [`ChaintoolsError`], which can be recovered with
+[`anyhow::Error::downcast_ref`] to inspect the failing line, byte offset and chain.
+*/
+
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+use crate::cmap::chain::ChainId;
+
+/// Errors raised by chaintools.
+///
+/// Line numbers are 1-based and byte offsets are 0-based; both refer to the
+/// (decompressed) input when known. Parsers working on detached header or block
+/// slices report positions relative to those slices, which readers then shift
+/// to absolute positions in the file.
+///
+/// # Example
+/// ```
+/// use chaintools::error::ChaintoolsError;
+/// use chaintools::io::reader::Reader;
+///
+/// let data = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n2x5\n\n";
+/// let err = Reader::from_bytes(data).unwrap_err();
+///
+/// match err.downcast_ref::<ChaintoolsError>() {
+///     Some(ChaintoolsError::BadBlock { line, chain_id, .. }) => {
+///         assert_eq!(*line, Some(3));
+///         assert_eq!(*chain_id, Some(1));
+///     }
+///     _ => panic!("unexpected error: {}", err),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ChaintoolsError {
+    /// A chain header line could not be parsed
+    MalformedHeader {
+        line: Option<u64>,
+        offset: Option<u64>,
+        chain_id: Option<ChainId>,
+        message: String,
+    },
+    /// An alignment block line could not be parsed
+    BadBlock {
+        line: Option<u64>,
+        offset: Option<u64>,
+        chain_id: Option<ChainId>,
+        message: String,
+    },
+    /// A chain ID is used by more than one chain; the position is the one of the repeated header
+    DuplicateChain {
+        chain_id: ChainId,
+        line: Option<u64>,
+        offset: Option<u64>,
+    },
+    /// A requested chain is not present in a file or index
+    MissingChain {
+        chain_id: ChainId,
+        path: Option<PathBuf>,
+    },
+    /// An index or binary container does not match the expected format or source file
+    IndexMismatch {
+        path: PathBuf,
+        message: String,
+    },
+    /// An underlying I/O error
+    Io {
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+}
+
+impl ChaintoolsError {
+    /// Create an I/O error for a path.
+    ///
+    /// # Arguments
+    /// * `path` - A path the error relates to
+    /// * `source` - An I/O error
+    ///
+    /// # Returns
+    /// * ChaintoolsError
+    pub fn io<P: Into<PathBuf>>(path: P, source: std::io::Error) -> Self {
+        Self::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// Get the 1-based line number of the error, if known.
+    ///
+    /// # Returns
+    /// * Option<u64>
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            Self::MalformedHeader { line, .. }
+            | Self::BadBlock { line, .. }
+            | Self::DuplicateChain { line, .. } => *line,
+            _ => None,
+        }
+    }
+
+    /// Get the 0-based byte offset of the error, if known.
+    ///
+    /// # Returns
+    /// * Option<u64>
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Self::MalformedHeader { offset, .. }
+            | Self::BadBlock { offset, .. }
+            | Self::DuplicateChain { offset, .. } => *offset,
+            _ => None,
+        }
+    }
+
+    /// Get the ID of the chain the error relates to, if known.
+    ///
+    /// # Returns
+    /// * Option<ChainId>
+    pub fn chain_id(&self) -> Option<ChainId> {
+        match self {
+            Self::MalformedHeader { chain_id, .. } | Self::BadBlock { chain_id, .. } => *chain_id,
+            Self::DuplicateChain { chain_id, .. } | Self::MissingChain { chain_id, .. } => Some(*chain_id),
+            _ => None,
+        }
+    }
+
+    /// Shift a position relative to a header or block slice to an absolute one.
+    ///
+    /// # Arguments
+    /// * `lines` - Number of lines preceding the slice, `None` if unknown
+    /// * `bytes` - Byte offset of the slice, `None` if unknown
+    ///
+    /// # Returns
+    /// * ChaintoolsError
+    pub(crate) fn shifted(mut self, lines: Option<u64>, bytes: Option<u64>) -> Self {
+        if let Self::MalformedHeader { line, offset, .. } | Self::BadBlock { line, offset, .. } = &mut self {
+            *line = lines.map(|x| line.unwrap_or(1) + x);
+            *offset = bytes.map(|x| offset.unwrap_or(0) + x);
+        }
+        self
+    }
+
+    /// Attach a chain ID if the error does not carry one yet.
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    ///
+    /// # Returns
+    /// * ChaintoolsError
+    pub(crate) fn with_chain(mut self, id: ChainId) -> Self {
+        if let Self::MalformedHeader { chain_id, .. } | Self::BadBlock { chain_id, .. } = &mut self {
+            chain_id.get_or_insert(id);
+        }
+        self
+    }
+}
+
+impl Display for ChaintoolsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // location suffix shared by the parsing errors
+        let location = |line: &Option<u64>, offset: &Option<u64>, chain_id: &Option<ChainId>| {
+            let mut loc = String::new();
+            if let Some(line) = line {
+                loc.push_str(&format!(" at line {}", line));
+            }
+            if let Some(offset) = offset {
+                loc.push_str(&format!(" (byte {})", offset));
+            }
+            if let Some(id) = chain_id {
+                loc.push_str(&format!(" in chain {}", id));
+            }
+            loc
+        };
+        match self {
+            Self::MalformedHeader { line, offset, chain_id, message } => {
+                write!(f, "Malformed chain header{}: {}", location(line, offset, chain_id), message)
+            }
+            Self::BadBlock { line, offset, chain_id, message } => {
+                write!(f, "Bad alignment block line{}: {}", location(line, offset, chain_id), message)
+            }
+            Self::DuplicateChain { chain_id, line, offset } => {
+                write!(f, "Duplicate chain ID {}{}", chain_id, location(line, offset, &None))
+            }
+            Self::MissingChain { chain_id, path: Some(path) } => {
+                write!(f, "Chain {} is missing from {:?}", chain_id, path)
+            }
+            Self::MissingChain { chain_id, path: None } => write!(f, "Chain {} is missing", chain_id),
+            Self::IndexMismatch { path, message } => write!(f, "{:?}: {}", path, message),
+            Self::Io { path: Some(path), source } => write!(f, "I/O error on {:?}: {}", path, source),
+            Self::Io { path: None, source } => write!(f, "I/O error: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for ChaintoolsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ChaintoolsError {
+    fn from(source: std::io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+impl Clone for ChaintoolsError {
+    fn clone(&self) -> Self {
+        match self {
+            Self::MalformedHeader { line, offset, chain_id, message } => Self::MalformedHeader {
+                line: *line,
+                offset: *offset,
+                chain_id: *chain_id,
+                message: message.clone(),
+            },
+            Self::BadBlock { line, offset, chain_id, message } => Self::BadBlock {
+                line: *line,
+                offset: *offset,
+                chain_id: *chain_id,
+                message: message.clone(),
+            },
+            Self::DuplicateChain { chain_id, line, offset } => Self::DuplicateChain {
+                chain_id: *chain_id,
+                line: *line,
+                offset: *offset,
+            },
+            Self::MissingChain { chain_id, path } => Self::MissingChain { chain_id: *chain_id, path: path.clone() },
+            Self::IndexMismatch { path, message } => Self::IndexMismatch { path: path.clone(), message: message.clone() },
+            // I/O errors cannot be cloned; the copy keeps their kind and message
+            Self::Io { path, source } => Self::Io {
+                path: path.clone(),
+                source: std::io::Error::new(source.kind(), source.to_string()),
+            },
+        }
+    }
+}
@@ -4,5 +4,10 @@ Contains the IO module for the library.
 
 #[cfg(feature = "read")]
 pub mod indexer;
+pub mod bgzf;
+pub mod codec;
+pub mod compress;
+pub mod parse;
 pub mod reader;
+pub mod sort;
 pub mod writer;
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression, Crc};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+
+/// Maximum number of uncompressed bytes stored in a single BGZF block
+const BLOCK_DATA_SIZE: usize = 0xff00;
+/// Maximum size of a compressed BGZF block
+const MAX_BLOCK_SIZE: usize = 0x10000;
+/// Size of a BGZF block header (gzip header with the `BC` extra subfield)
+const HEADER_SIZE: usize = 18;
+/// Size of a BGZF block footer (CRC32 and uncompressed size)
+const FOOTER_SIZE: usize = 8;
+/// Empty block terminating every BGZF file
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02,
+    0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Check whether a byte slice starts with a BGZF block header
+///
+/// # Arguments
+/// * `data` - The first bytes of a file
+///
+/// # Returns
+/// * bool
+///
+/// # Example
+/// ```
+/// use chaintools::io::bgzf::is_bgzf;
+///
+/// let mut out = chaintools::io::bgzf::BgzfWriter::new(Vec::new());
+/// std::io::Write::write_all(&mut out, b"chain").unwrap();
+/// let data = out.finish().unwrap();
+///
+/// assert!(is_bgzf(&data));
+/// assert!(!is_bgzf(b"chain"));
+/// ```
+pub fn is_bgzf(data: &[u8]) -> bool {
+    data.len() >= HEADER_SIZE
+        && data[0] == 0x1f
+        && data[1] == 0x8b
+        && data[2] == 0x08
+        && data[3] & 0x04 != 0
+        && u16::from_le_bytes([data[10], data[11]]) >= 6
+        && data[12] == b'B'
+        && data[13] == b'C'
+        && u16::from_le_bytes([data[14], data[15]]) == 2
+}
+
+/// A writer producing BGZF (blocked gzip) streams
+///
+/// BGZF files are a series of gzip members, so they can still be decompressed
+/// with any gzip reader, while their blocks can be located through virtual
+/// offsets: `compressed block start << 16 | offset within the uncompressed block`.
+pub struct BgzfWriter<W: Write> {
+    inner: Option<W>,
+    buffer: Vec<u8>,
+    compressed: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    /// Create a new BGZF writer
+    ///
+    /// # Arguments
+    /// * `inner` - A writer receiving compressed blocks
+    ///
+    /// # Returns
+    /// * BgzfWriter
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: Some(inner),
+            buffer: Vec::with_capacity(BLOCK_DATA_SIZE),
+            compressed: 0,
+        }
+    }
+
+    /// Get the virtual offset of the next byte to be written
+    ///
+    /// # Returns
+    /// * u64
+    pub fn virtual_offset(&self) -> u64 {
+        (self.compressed << 16) | self.buffer.len() as u64
+    }
+
+    /// Compress and write the buffered data as a single block
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let mut cdata = Self::deflate(&self.buffer, Compression::default())?;
+        if cdata.len() + HEADER_SIZE + FOOTER_SIZE > MAX_BLOCK_SIZE {
+            // incompressible data; store it as is
+            cdata = Self::deflate(&self.buffer, Compression::none())?;
+        }
+        let mut crc = Crc::new();
+        crc.update(&self.buffer);
+        let block_size = cdata.len() + HEADER_SIZE + FOOTER_SIZE;
+
+        let inner = self.inner.as_mut().expect("BGZF writer used after finish");
+        let mut header = [0u8; HEADER_SIZE];
+        header[..HEADER_SIZE - 2].copy_from_slice(&EOF_BLOCK[..HEADER_SIZE - 2]);
+        header[HEADER_SIZE - 2..].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+        inner.write_all(&header)?;
+        inner.write_all(&cdata)?;
+        inner.write_all(&crc.sum().to_le_bytes())?;
+        inner.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+
+        self.compressed += block_size as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Raw deflate compression of a block
+    fn deflate(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+        let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len()), level);
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    /// Flush the remaining data, write the EOF marker block and return the inner writer
+    ///
+    /// # Returns
+    /// * Result<W>
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block().context("Failed to write BGZF block")?;
+        let mut inner = self.inner.take().expect("BGZF writer used after finish");
+        inner.write_all(&EOF_BLOCK).context("Failed to write BGZF EOF block")?;
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_DATA_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == BLOCK_DATA_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.write_block();
+            if let Some(inner) = self.inner.as_mut() {
+                let _ = inner.write_all(&EOF_BLOCK);
+                let _ = inner.flush();
+            }
+        }
+    }
+}
+
+/// A reader for BGZF streams supporting seeks to virtual offsets
+pub struct BgzfReader<R: Read + Seek> {
+    inner: R,
+    block: Vec<u8>,
+    pos: usize,
+    block_start: u64,
+    next_block: u64,
+}
+
+impl<R: Read + Seek> BgzfReader<R> {
+    /// Create a new BGZF reader positioned at the start of the stream
+    ///
+    /// # Arguments
+    /// * `inner` - A seekable source of BGZF data
+    ///
+    /// # Returns
+    /// * BgzfReader
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::bgzf::{BgzfReader, BgzfWriter};
+    /// use std::io::{Cursor, Read, Write};
+    ///
+    /// let mut out = BgzfWriter::new(Vec::new());
+    /// out.write_all(b"chain").unwrap();
+    /// let mut data = out.finish().unwrap();
+    ///
+    /// let mut text = String::new();
+    /// BgzfReader::new(Cursor::new(&data)).read_to_string(&mut text).unwrap();
+    /// assert_eq!(text, "chain");
+    ///
+    /// // blocks claiming more than 64 kb of data are rejected before anything is allocated
+    /// let isize = data.len() - 28 - 4;
+    /// data[isize..isize + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    /// assert!(BgzfReader::new(Cursor::new(&data)).read_to_string(&mut text).is_err());
+    /// ```
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            block: Vec::new(),
+            pos: 0,
+            block_start: 0,
+            next_block: 0,
+        }
+    }
+
+    /// Get the virtual offset of the next byte to be read
+    ///
+    /// # Returns
+    /// * u64
+    pub fn virtual_offset(&self) -> u64 {
+        if self.pos == self.block.len() {
+            // the current block is exhausted; the next byte lies in the next block
+            return self.next_block << 16;
+        }
+        (self.block_start << 16) | self.pos as u64
+    }
+
+    /// Move to a virtual offset
+    ///
+    /// # Arguments
+    /// * `offset` - A virtual offset
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub fn seek_virtual(&mut self, offset: u64) -> Result<()> {
+        let block_start = offset >> 16;
+        let within = (offset & 0xffff) as usize;
+        if block_start != self.block_start || self.block.is_empty() {
+            self.inner.seek(SeekFrom::Start(block_start))?;
+            self.next_block = block_start;
+            self.read_block()?;
+        }
+        if within > self.block.len() {
+            anyhow::bail!("Virtual offset {} points past the end of its BGZF block", offset);
+        }
+        self.pos = within;
+        Ok(())
+    }
+
+    /// Read and decompress the next block; leaves an empty block at EOF
+    fn read_block(&mut self) -> io::Result<()> {
+        self.block.clear();
+        self.pos = 0;
+        self.block_start = self.next_block;
+
+        let mut header = [0u8; HEADER_SIZE];
+        match self.inner.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if !is_bgzf(&header) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid BGZF block header"));
+        }
+        let xlen = u16::from_le_bytes([header[10], header[11]]) as usize;
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+        // skip any extra subfields following the `BC` one
+        let mut extra = vec![0u8; xlen - 6];
+        self.inner.read_exact(&mut extra)?;
+        let cdata_size = block_size
+            .checked_sub(HEADER_SIZE + extra.len() + FOOTER_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid BGZF block size"))?;
+
+        let mut cdata = vec![0u8; cdata_size];
+        self.inner.read_exact(&mut cdata)?;
+        let mut footer = [0u8; FOOTER_SIZE];
+        self.inner.read_exact(&mut footer)?;
+        let crc = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+        let isize = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as usize;
+
+        // blocks hold at most 64 kb of data, whatever their footer claims
+        if isize > MAX_BLOCK_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid BGZF uncompressed block size"));
+        }
+        self.block.reserve(isize);
+        DeflateDecoder::new(&cdata[..]).take(isize as u64 + 1).read_to_end(&mut self.block)?;
+        let mut check = Crc::new();
+        check.update(&self.block);
+        if self.block.len() != isize || check.sum() != crc {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Corrupted BGZF block"));
+        }
+        self.next_block = self.block_start + block_size as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // skip empty blocks (including the EOF marker) until data or the end of stream is met
+        while self.pos == self.block.len() {
+            let previous = self.next_block;
+            self.read_block()?;
+            if self.block.is_empty() && self.next_block == previous {
+                break;
+            }
+        }
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.block.len());
+    }
+}
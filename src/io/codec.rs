@@ -0,0 +1,319 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::cmap::align::AlignmentRecord;
+use crate::cmap::chain::{Chain, ChainHead, ChainId};
+use crate::error::ChaintoolsError;
+use crate::io::indexer::ChainOffsets;
+
+/// Compact binary encoding of chains used by the binary container
+///
+/// Integers are stored as LEB128 varints, chain ends as their distance from
+/// the start, and the presence of non-zero `dt`/`dq` gaps as two bits per block,
+/// so ungapped sides cost nothing. Decoding an encoded chain always gives back
+/// a chain with the very same text representation.
+///
+/// Layout of an encoded chain:
+/// * `id`, `score`
+/// * reference and query heads: `chr` length and bytes, `size`, strand length and bytes, `start`, `end - start`
+/// * number of blocks, gap flags bitmap, `is_last` marker (0 if only the final
+///   block is the last one, 1 followed by an explicit bitmap otherwise)
+/// * per block: `size`, then `dt` and `dq` if flagged
+///
+/// # Example
+/// ```
+/// use chaintools::io::codec::{decode_chain, encode_chain};
+/// use chaintools::io::reader::Reader;
+///
+/// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+/// let chains = Reader::from_bytes(line).unwrap();
+/// let chain = chains.get(&1).unwrap();
+///
+/// let mut encoded: Vec<u8> = Vec::new();
+/// encode_chain(chain, &mut encoded);
+/// let decoded = decode_chain(&encoded).unwrap();
+///
+/// assert_eq!(decoded.to_string(), chain.to_string());
+/// ```
+pub fn encode_chain(chain: &Chain, out: &mut Vec<u8>) {
+    write_varint(out, chain.id);
+    write_varint(out, chain.score);
+    encode_head(&chain.refs, out);
+    encode_head(&chain.query, out);
+
+    let blocks = &chain.alignment;
+    write_varint(out, blocks.len() as u64);
+    let mut flags = vec![0u8; (blocks.len() * 2).div_ceil(8)];
+    for (i, block) in blocks.iter().enumerate() {
+        if block.dt != 0 {
+            flags[i * 2 / 8] |= 1 << (i * 2 % 8);
+        }
+        if block.dq != 0 {
+            flags[(i * 2 + 1) / 8] |= 1 << ((i * 2 + 1) % 8);
+        }
+    }
+    out.extend_from_slice(&flags);
+
+    let canonical = blocks
+        .iter()
+        .enumerate()
+        .all(|(i, block)| block.is_last == (i + 1 == blocks.len()));
+    if canonical {
+        out.push(0);
+    } else {
+        out.push(1);
+        let mut last = vec![0u8; blocks.len().div_ceil(8)];
+        for (i, block) in blocks.iter().enumerate() {
+            if block.is_last {
+                last[i / 8] |= 1 << (i % 8);
+            }
+        }
+        out.extend_from_slice(&last);
+    }
+
+    for block in blocks {
+        write_varint(out, block.size as u64);
+        if block.dt != 0 {
+            write_varint(out, block.dt as u64);
+        }
+        if block.dq != 0 {
+            write_varint(out, block.dq as u64);
+        }
+    }
+}
+
+/// Decode a chain written by [`encode_chain`]
+///
+/// Records are checked against their own length before anything is allocated,
+/// so truncated or corrupted records fail instead of panicking.
+///
+/// # Arguments
+/// * `data` - A single encoded chain
+///
+/// # Returns
+/// * Result<Chain>
+///
+/// # Example
+/// ```
+/// use chaintools::cmap::align::AlignmentRecord;
+/// use chaintools::io::codec::{decode_chain, encode_chain};
+/// use chaintools::io::reader::Reader;
+///
+/// let data = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n10\t0\t3\n48\t1\t0\n166\n\n\
+/// chain 1000 chr1 1000000 + 100 300 chrA 5000 + 1000 1210 2\n50\t10\t20\n140\n\n\
+/// chain 20 chr2 50000 + 10 40 chrA 5000 + 10 40 3\n30\n\n";
+/// let chains = Reader::from_bytes(data).unwrap();
+/// let blocks = |chain: &chaintools::cmap::chain::Chain| -> Vec<(u32, u32, u32, bool)> {
+///     chain.alignment.iter().map(|x| (x.size, x.dt, x.dq, x.is_last)).collect()
+/// };
+///
+/// // every chain decodes to the same text and blocks
+/// for chain in chains.values() {
+///     let mut encoded: Vec<u8> = Vec::new();
+///     encode_chain(chain, &mut encoded);
+///     let decoded = decode_chain(&encoded).unwrap();
+///     assert_eq!(decoded.to_string(), chain.to_string());
+///     assert_eq!(blocks(&decoded), blocks(chain));
+///     assert!(decode_chain(&encoded[..encoded.len() - 1]).is_err());
+/// }
+///
+/// // so do blocks at the u32 limits and `is_last` flags away from the final block
+/// let mut chain = chains.get(&2).unwrap().clone();
+/// chain.alignment = vec![
+///     AlignmentRecord::new(u32::MAX, u32::MAX, 0, true),
+///     AlignmentRecord::new(1, 0, u32::MAX, false),
+/// ].into();
+/// let mut encoded: Vec<u8> = Vec::new();
+/// encode_chain(&chain, &mut encoded);
+/// assert_eq!(blocks(&decode_chain(&encoded).unwrap()), blocks(&chain));
+///
+/// // crafted block counts and truncated gap flags are errors
+/// let head = [0u8, 0, 1, b'+', 0, 0];
+/// let record = |count: &[u8], rest: &[u8]| [&[1u8, 0][..], &head, &head, count, rest].concat();
+/// assert_eq!(decode_chain(&record(&[1], &[0, 0, 5])).unwrap().alignment[0].size, 5);
+/// let huge = [0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01];
+/// assert!(decode_chain(&record(&huge, &[0; 16])).is_err());
+/// assert!(decode_chain(&record(&[20], &[0xff])).is_err());
+/// ```
+pub fn decode_chain(data: &[u8]) -> Result<Chain> {
+    let mut data = data;
+    let id = read_varint(&mut data).context("Failed to decode chain ID")?;
+    let score = read_varint(&mut data).context("Failed to decode chain score")?;
+    let refs = decode_head(&mut data).context("Failed to decode reference head")?;
+    let query = decode_head(&mut data).context("Failed to decode query head")?;
+
+    // every block takes at least one byte, which bounds the count of a corrupted record
+    let count = read_varint(&mut data).context("Failed to decode block count")?;
+    let n = usize::try_from(count)
+        .ok()
+        .filter(|n| *n <= data.len())
+        .with_context(|| format!("Invalid block count {} in encoded chain {}", count, id))?;
+    let bits = n.checked_mul(2).with_context(|| format!("Invalid block count {} in encoded chain {}", count, id))?;
+    let flags = take(&mut data, bits.div_ceil(8)).context("Failed to decode gap flags")?;
+    let last = match take(&mut data, 1).context("Failed to decode block marker")?[0] {
+        0 => None,
+        1 => Some(take(&mut data, n.div_ceil(8)).context("Failed to decode block marker")?),
+        x => anyhow::bail!("Invalid block marker {} in encoded chain {}", x, id),
+    };
+
+    let mut alignment: Vec<AlignmentRecord> = Vec::with_capacity(n.min(data.len()));
+    for i in 0..n {
+        let size = read_u32(&mut data)?;
+        let dt = if flags[i * 2 / 8] & (1 << (i * 2 % 8)) != 0 { read_u32(&mut data)? } else { 0 };
+        let dq = if flags[(i * 2 + 1) / 8] & (1 << ((i * 2 + 1) % 8)) != 0 { read_u32(&mut data)? } else { 0 };
+        let is_last = match last {
+            None => i + 1 == n,
+            Some(bits) => bits[i / 8] & (1 << (i % 8)) != 0,
+        };
+        alignment.push(AlignmentRecord::new(size, dt, dq, is_last));
+    }
+    if !data.is_empty() {
+        anyhow::bail!("{} trailing bytes after encoded chain {}", data.len(), id);
+    }
+
+    Ok(Chain { score, refs, query, alignment: alignment.into(), id })
+}
+
+/// Append an unsigned LEB128 varint
+///
+/// # Arguments
+/// * `out` - An output buffer
+/// * `value` - A value to encode
+///
+/// # Example
+/// ```
+/// use chaintools::io::codec::{read_varint, write_varint};
+///
+/// let mut out: Vec<u8> = Vec::new();
+/// write_varint(&mut out, 300);
+/// assert_eq!(out, vec![0xac, 0x02]);
+/// assert_eq!(read_varint(&mut &out[..]).unwrap(), 300);
+/// ```
+pub fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read an unsigned LEB128 varint, advancing the slice past it
+///
+/// # Arguments
+/// * `data` - A byte slice starting with a varint
+///
+/// # Returns
+/// * Result<u64> - an error if the varint is truncated or does not fit into 64 bits
+///
+/// # Example
+/// ```
+/// use chaintools::io::codec::read_varint;
+///
+/// let max = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+/// assert_eq!(read_varint(&mut &max[..]).unwrap(), u64::MAX);
+///
+/// let overflow = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02];
+/// assert!(read_varint(&mut &overflow[..]).is_err());
+/// ```
+pub fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut value: u64 = 0;
+    for (i, byte) in data.iter().enumerate() {
+        // the 10th byte holds the 64th bit only and must end the varint
+        if i == 9 && *byte > 1 {
+            anyhow::bail!("Varint overflows 64 bits");
+        }
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *data = &data[i + 1..];
+            return Ok(value);
+        }
+    }
+    anyhow::bail!("Truncated varint")
+}
+
+/// Encode a container footer table of `(chain_id, record_length)` pairs
+///
+/// # Arguments
+/// * `table` - Chain IDs and record lengths in the order the records were written
+/// * `out` - An output buffer
+pub(crate) fn encode_table(table: &[(ChainId, u64)], out: &mut Vec<u8>) {
+    write_varint(out, table.len() as u64);
+    for (id, len) in table {
+        write_varint(out, *id);
+        write_varint(out, *len);
+    }
+}
+
+/// Decode a container footer table into a `{chain_id: (offset, length)}` map
+///
+/// # Arguments
+/// * `data` - An encoded table
+/// * `first` - Offset of the first record in the container
+/// * `path` - Path of the container, for error reporting
+///
+/// # Returns
+/// * Result<ChainOffsets> - an [`ChaintoolsError::IndexMismatch`] error if a chain ID
+///   is listed twice or record offsets overflow
+pub(crate) fn decode_table(data: &[u8], first: u64, path: &Path) -> Result<ChainOffsets> {
+    let corrupted = |message: String| ChaintoolsError::IndexMismatch { path: path.to_path_buf(), message };
+    let mut data = data;
+    let n = read_varint(&mut data)? as usize;
+    let mut table: ChainOffsets = ChainOffsets::default();
+    table.reserve(n.min(data.len()));
+    let mut offset = first;
+    for _ in 0..n {
+        let id = read_varint(&mut data)?;
+        let len = read_varint(&mut data)?;
+        if table.insert(id, (offset, len)).is_some() {
+            return Err(corrupted(format!("binary container lists chain {} more than once", id)).into());
+        }
+        offset = offset
+            .checked_add(len)
+            .ok_or_else(|| corrupted(format!("record length of chain {} overflows the container offsets", id)))?;
+    }
+    Ok(table)
+}
+
+// Private varint reader for u32 fields
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+    let value = read_varint(data)?;
+    u32::try_from(value).with_context(|| format!("Value {} does not fit into u32", value))
+}
+
+// Private reader for a fixed number of bytes
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Result<&'a [u8]> {
+    if data.len() < n {
+        anyhow::bail!("Unexpected end of encoded chain");
+    }
+    let (head, tail) = data.split_at(n);
+    *data = tail;
+    Ok(head)
+}
+
+// Private encoder for chain heads
+fn encode_head(head: &ChainHead, out: &mut Vec<u8>) {
+    write_varint(out, head.chr.len() as u64);
+    out.extend_from_slice(head.chr.as_bytes());
+    write_varint(out, head.size);
+    let mut strand = [0u8; 4];
+    let strand = head.strand.encode_utf8(&mut strand);
+    write_varint(out, strand.len() as u64);
+    out.extend_from_slice(strand.as_bytes());
+    write_varint(out, head.start);
+    write_varint(out, head.end.wrapping_sub(head.start));
+}
+
+// Private decoder for chain heads
+fn decode_head(data: &mut &[u8]) -> Result<ChainHead> {
+    let len = read_varint(data)? as usize;
+    let chr = std::str::from_utf8(take(data, len)?)?.to_string();
+    let size = read_varint(data)?;
+    let len = read_varint(data)? as usize;
+    let strand = std::str::from_utf8(take(data, len)?)?
+        .chars()
+        .next()
+        .context("Missing strand")?;
+    let start = read_varint(data)?;
+    let end = start.wrapping_add(read_varint(data)?);
+    Ok(ChainHead { chr, size, strand, start, end })
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use flate2::{bufread::MultiGzDecoder, write::GzEncoder};
+use std::io::{BufRead, BufReader, Write};
+
+use crate::io::bgzf::{is_bgzf, BgzfWriter};
+
+/// Compression formats of chain files and binary containers
+///
+/// Readers detect the format from the leading magic bytes of the data, never from
+/// file extensions. gzip and BGZF are always supported; zstd, bzip2 and xz need
+/// the `zstd`, `bzip2` and `xz` features respectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    /// Uncompressed data
+    #[default]
+    None,
+    /// gzip, including multi-member files
+    Gzip,
+    /// Blocked gzip, readable as gzip and indexable by virtual offsets
+    Bgzf,
+    /// Zstandard
+    Zstd,
+    /// bzip2
+    Bzip2,
+    /// xz (LZMA2)
+    Xz,
+}
+
+impl Compression {
+    /// Detect the compression format from the first bytes of the data
+    ///
+    /// # Arguments
+    /// * `magic` - The first bytes of the data; 18 bytes are enough to tell BGZF from gzip
+    ///
+    /// # Returns
+    /// * Compression
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::compress::Compression;
+    ///
+    /// assert_eq!(Compression::detect(b"chain 4900 chrY"), Compression::None);
+    /// assert_eq!(Compression::detect(&[0x1f, 0x8b, 0x08, 0x00]), Compression::Gzip);
+    /// assert_eq!(Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]), Compression::Zstd);
+    /// ```
+    pub fn detect(magic: &[u8]) -> Self {
+        if is_bgzf(magic) {
+            Self::Bgzf
+        } else if magic.starts_with(&[0x1f, 0x8b]) {
+            Self::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Self::Zstd
+        } else if magic.starts_with(b"BZh") {
+            Self::Bzip2
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Self::Xz
+        } else {
+            Self::None
+        }
+    }
+
+    /// Wrap a buffered source with a decoder for its compression format,
+    /// detected by peeking at its first bytes
+    ///
+    /// # Arguments
+    /// * `reader` - A buffered source of plain or compressed data
+    ///
+    /// # Returns
+    /// * Result<Box<dyn BufRead>> - a source of decompressed data
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::compress::Compression;
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    /// gz.write_all(b"chain").unwrap();
+    /// let gz = gz.finish().unwrap();
+    ///
+    /// let mut data = String::new();
+    /// Compression::decompress(&gz[..]).unwrap().read_to_string(&mut data).unwrap();
+    /// assert_eq!(data, "chain");
+    /// ```
+    pub fn decompress<'a, R: BufRead + 'a>(mut reader: R) -> Result<Box<dyn BufRead + 'a>> {
+        let format = Self::detect(reader.fill_buf()?);
+        Ok(match format {
+            Self::None => Box::new(reader),
+            Self::Gzip | Self::Bgzf => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(reader))),
+            #[cfg(feature = "xz")]
+            Self::Xz => Box::new(BufReader::new(xz2::bufread::XzDecoder::new_multi_decoder(reader))),
+            #[allow(unreachable_patterns)]
+            other => return Err(other.unsupported()),
+        })
+    }
+
+    /// Compress everything written by a closure into a writer
+    ///
+    /// The encoder is properly finished once the closure returns, so compressed
+    /// streams are never left truncated.
+    ///
+    /// # Arguments
+    /// * `out` - A writer receiving compressed data
+    /// * `f` - A closure writing plain data
+    ///
+    /// # Returns
+    /// * Result<W> - the inner writer
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::compress::Compression;
+    ///
+    /// let out = Compression::Gzip.compress(Vec::new(), |w| Ok(w.write_all(b"chain")?)).unwrap();
+    /// assert_eq!(Compression::detect(&out), Compression::Gzip);
+    /// ```
+    pub fn compress<W, F>(self, mut out: W, f: F) -> Result<W>
+    where
+        W: Write,
+        F: FnOnce(&mut dyn Write) -> Result<()>,
+    {
+        match self {
+            Self::None => {
+                f(&mut out)?;
+                Ok(out)
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(out, flate2::Compression::default());
+                f(&mut encoder)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Bgzf => {
+                let mut encoder = BgzfWriter::new(out);
+                f(&mut encoder)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(out, 0)?;
+                f(&mut encoder)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "bzip2")]
+            Self::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(out, bzip2::Compression::default());
+                f(&mut encoder)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "xz")]
+            Self::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(out, 6);
+                f(&mut encoder)?;
+                Ok(encoder.finish()?)
+            }
+            #[allow(unreachable_patterns)]
+            other => Err(other.unsupported()),
+        }
+    }
+
+    // Private error for formats whose feature is disabled
+    #[allow(dead_code)]
+    fn unsupported(self) -> anyhow::Error {
+        let feature = match self {
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            _ => "xz",
+        };
+        anyhow::anyhow!(
+            "{:?} compression is not supported; enable the `{}` feature of chaintools",
+            self, feature
+        )
+    }
+}
@@ -1,11 +1,24 @@
 use anyhow::{Context, Result};
-use flate2::read::MultiGzDecoder;
 use fxhash::FxHashMap;
-use memchr::{memchr, memrchr};
-use std::{fmt::{Debug, Display}, fs::File,  io::{Read, Write}, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{fmt::{Debug, Display}, fs::File,  io::{BufRead, BufReader, BufWriter, Read, Write}, path::Path, time::UNIX_EPOCH};
 
+use crate::cmap::chain::{BlockSide, Chain, ChainHead, ChainId};
+use crate::cmap::map::ChainMap;
+use crate::error::ChaintoolsError;
+use crate::io::bgzf::BgzfReader;
+use crate::io::compress::Compression;
+use crate::io::reader::{Reader, Records};
 // use crate::io::writer::Writer;
 
+/// Magic bytes opening every chaintools index file
+pub const INDEX_MAGIC: &[u8; 8] = b"CHAINIDX";
+/// Current version of the index file layout
+pub const INDEX_VERSION: u16 = 2;
+
+/// {chain_id: (first_byte, last_byte)} map of chain locations in a file
+pub type ChainOffsets = FxHashMap<ChainId, (u64, u64)>;
+
 /// Binary indexer for chain file
 
 #[derive(Debug)]
@@ -27,28 +40,23 @@ impl BinaryIndex{
     where
         T: AsRef<Path> + Debug,
     {
-        let stat = std::fs::metadata(&file)
-            .with_context(|| format!("Failed to get metadata for {:?}", file))?;
-        let mut data = vec![];
-        data.reserve(stat.len() as usize + 1);
-
-        let mut f = File::open(&file).with_context(|| format!("Failed to open file {:?}", file))?;
-
-        if file.as_ref().extension().unwrap() == "gz" {
-            let mut decoder = MultiGzDecoder::new(f);
-            decoder
-                .read_to_end(&mut data)
-                .with_context(|| format!("Failed to read file {:?}", file))?;
-        } else {
-            f.read_to_end(&mut data)
-                .with_context(|| format!("Failed to read file {:?}", file))?;
-        }
-
-        Ok(data)
+        let path = file.as_ref();
+        let stat = std::fs::metadata(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        Reader::read_source(f, stat.len() as usize + 1, Some(path))
     }
 
     /// Creates a binary index of a file
     /// 
+    /// The index file stores byte offsets of every chain, a region index of reference
+    /// and query spans used by [`crate::io::reader::Reader::fetch_region`] and
+    /// [`crate::io::reader::Reader::fetch_query_region`], and the size, modification
+    /// time and checksum of the chain file used to detect stale indices
+    /// 
+    /// BGZF-compressed files (see [`crate::io::writer::Writer::to_chain_bgzf`]) are
+    /// indexed with virtual offsets; plain gzip files cannot be seeked into and are refused.
+    /// Files reusing chain IDs are refused as well, as an index holds a single chain per ID
+    /// 
     /// # Arguments
     /// 
     /// * `file` - A path to a chain file
@@ -67,95 +75,691 @@ impl BinaryIndex{
     pub fn index<U>(file: U) -> Result<()>
     where
         U: AsRef<Path> + Debug + Display,
+    {
+        let out_file: String = format!("{}.ix", &file);
+        let bgzf = Self::is_compressed(&file)?;
+        let (offsets, regions) = if bgzf {
+            Self::scan_bgzf(&file)?
+        } else {
+            Self::scan(&file)?
+        };
+
+        // serialize the results along with the source file stamp and exit
+        let index = ChainIndex {
+            source: SourceStamp::of(&file)?,
+            bgzf,
+            offsets,
+            regions,
+        };
+        index.write(out_file)
+    }
+
+    // Private compression sniffer; returns true for BGZF files and fails for other gzip files
+    fn is_compressed<U>(file: U) -> Result<bool>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let path = file.as_ref();
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let mut magic: Vec<u8> = Vec::with_capacity(18);
+        f.take(18).read_to_end(&mut magic)
+            .map_err(|e| ChaintoolsError::io(path, e))?;
+        match Compression::detect(&magic) {
+            Compression::None => Ok(false),
+            Compression::Bgzf => Ok(true),
+            other => anyhow::bail!(
+                "{:?} is {:?}-compressed but not BGZF and cannot be indexed; rewrite it with Writer::to_chain_bgzf",
+                file, other
+            ),
+        }
+    }
+
+    // Private scanner for plain chain files recording byte offsets of every chain
+    fn scan<U>(file: U) -> Result<(ChainOffsets, RegionIndex)>
+    where
+        U: AsRef<Path> + Debug,
     {
         // open file as binary
         let data: Vec<u8> = Self::open(&file)?;
-        let mut data: &[u8] = &data[..];
+        let mut offsets: ChainOffsets = FxHashMap::default();
+        let mut regions: RegionIndex = RegionIndex::new();
+
+        // every chain spans from its header to the next header or the end of file
+        let records = Records::new(&data);
+        let mut previous: Option<(ChainId, usize)> = None;
+        for record in records.clone() {
+            let start_byte = record.header.as_ptr() as usize - data.as_ptr() as usize;
+            if let Some((chain, start)) = previous.take() {
+                offsets.insert(chain, (start as u64, start_byte as u64));
+            }
+            let (_, refs, query, chain) = Chain::head(record.header)
+                .map_err(|e| records.locate(e, &record))?;
+            if offsets.contains_key(&chain) {
+                let (line, offset) = records.position(&record);
+                return Err(ChaintoolsError::DuplicateChain { chain_id: chain, line, offset }.into());
+            }
+            regions.insert_heads(&refs, &query, chain);
+            previous = Some((chain, start_byte));
+        }
+        if let Some((chain, start)) = previous {
+            offsets.insert(chain, (start as u64, data.len() as u64));
+        }
+        Ok((offsets, regions))
+    }
+
+    // Private scanner for BGZF chain files recording virtual offsets of every chain
+    fn scan_bgzf<U>(file: U) -> Result<(ChainOffsets, RegionIndex)>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let path = file.as_ref();
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let mut reader = BgzfReader::new(BufReader::new(f));
+        let mut offsets: ChainOffsets = FxHashMap::default();
+        let mut regions: RegionIndex = RegionIndex::new();
+        let mut line: Vec<u8> = Vec::new();
+        let mut current: Option<(ChainId, u64)> = None;
+        // lines and uncompressed bytes read so far, used to locate errors
+        let mut lines: u64 = 0;
+        let mut bytes: u64 = 0;
 
-        // create the necessary variables for position tracking
-        let out_file: String = format!("{}.ix", &file);
-        let mut out_handle = File::create(&out_file[..])?;
-        // let mut results: Vec<(u64, usize, usize)> = Vec::new();
-        // let mut results: FxHashMap<u64, (usize, usize)> = FxHashMap::default();
-        let mut start_byte: usize = 0;
-        let mut end_byte: usize;
-        let mut header_end: usize = 0;
-        let mut offset: usize = 0;
-        // let mut curr_byte: u64 = 0;
-        let mut chain: u64 = 0;
-        let mut chain_encountered: bool = false;
-        // here goes the indexing code
-
-        // iterate over file
         loop {
-            let Some(chain_start) = memchr(b'c', &data) else {
-                // record last byte to be the end byte for the last chain
-                end_byte = start_byte + header_end + 1+ data.len();
-                // results.insert(chain, (start_byte, end_byte));
-                writeln!(out_handle, "{}\t{}\t{}", chain, start_byte, end_byte)?; // TODO: Better error handling?
-                // our job is done here
+            // a chain ends where the next one starts or at the end of file
+            let position = reader.virtual_offset();
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)
+                .map_err(|e| ChaintoolsError::io(path, e))?;
+            if n == 0 || line.starts_with(b"chain") {
+                if let Some((chain, start_byte)) = current.take() {
+                    offsets.insert(chain, (start_byte, position));
+                }
+            }
+            if n == 0 {
                 break
-            };
-            // once the `chain` keyword is encountered, record the start byte
-            // if another chain record has been already encountered, save the start and end bytes for it
-            if chain_encountered {
-                end_byte = chain_start + offset;//start_byte + chain_start + header_end;//j  + header_end;
-                // results.insert(chain, (start_byte, end_byte));
-                writeln!(out_handle, "{}\t{}\t{}", chain, start_byte, end_byte)?; // TODO: Better error handling?
-                // results.push((chain, start_byte, end_byte));
             }
-            data = &data[chain_start..];
-            header_end = memchr(b'\n', &data).with_context(|| {
-                
-                format!(
-                    "Failed to find separator in: {:?}. Bad formatted line!",
-                    String::from_utf8_lossy(data)
-                )
-            })?;
-            let header = &data[..header_end];
-            let id_field: usize = memrchr(b' ', header).with_context(|| {
-                format!(
-                    "Improperly formatted header line: {:?}!",
-                    String::from_utf8_lossy(header)
-                )
+            if line.starts_with(b"chain") {
+                let header = line.strip_suffix(b"\n").unwrap_or(&line);
+                let (_, refs, query, chain) = Chain::head(header).map_err(|e| {
+                    match e.downcast::<ChaintoolsError>() {
+                        Ok(e) => e.shifted(Some(lines), Some(bytes)).into(),
+                        Err(e) => e,
+                    }
+                })?;
+                if offsets.contains_key(&chain) {
+                    return Err(ChaintoolsError::DuplicateChain {
+                        chain_id: chain,
+                        line: Some(lines + 1),
+                        offset: Some(bytes),
+                    }.into());
+                }
+                regions.insert_heads(&refs, &query, chain);
+                current = Some((chain, position));
             }
-            )?;
-            chain = std::str::from_utf8(&header[id_field+1..])
-                .unwrap()
-                .to_string()
-                .parse::<u64>()?;
-            start_byte = chain_start + offset; //+ header_end;
-            chain_encountered = true;
-            data = &data[header_end+1..];
-            offset += chain_start + header_end + 1;
+            lines += 1;
+            bytes += n as u64;
         }
+        Ok((offsets, regions))
+    }
+
+    /// Reads chain file index
+    /// 
+    /// Only the index format is checked; use [`BinaryIndex::load`] to also
+    /// make sure the index still matches its chain file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index_file` - A path to an index file
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing a `ChainIndex`
+    /// 
+    pub fn read_index<U>(index_file: U) -> Result<ChainIndex>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        ChainIndex::read(index_file)
+    }
+
+    /// Loads the index of a chain file, refusing indices which no longer match the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to an indexed chain file; the index is expected at `<file>.ix`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing a `ChainIndex`, or an error if the index is missing,
+    /// was written in an unsupported format, or is stale
+    /// 
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::indexer::BinaryIndex;
+    ///
+    /// let index = BinaryIndex::load("/path/to/file").unwrap();
+    /// println!("{} chains indexed", index.offsets.len());
+    /// ```
+    pub fn load<U>(file: U) -> Result<ChainIndex>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let index = ChainIndex::read(format!("{}.ix", &file))?;
+        if !index.source.matches(&file)? {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: format!("{}.ix", &file).into(),
+                message: String::from(
+                    "Index does not match its chain file anymore; rebuild it with BinaryIndex::index",
+                ),
+            }.into());
+        }
+        Ok(index)
+    }
+
+    /// Loads the index of a chain file, rebuilding it if it is missing, outdated or stale
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to a chain file; the index is expected at `<file>.ix`
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing a `ChainIndex`
+    /// 
+    pub fn load_or_build<U>(file: U) -> Result<ChainIndex>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        match Self::load(&file) {
+            Ok(index) => Ok(index),
+            Err(_) => {
+                Self::index(&file)?;
+                Self::load(&file)
+            }
+        }
+    }
+}
+
+/// Size, modification time and checksum of an indexed chain file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceStamp {
+    pub len: u64,
+    pub mtime_secs: u64,
+    pub mtime_nanos: u32,
+    pub checksum: u32,
+}
+
+impl SourceStamp {
+    /// Record the stamp of a file; the checksum is a CRC32 of its raw bytes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to a file
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing a `SourceStamp`
+    /// 
+    pub fn of<U>(file: U) -> Result<Self>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let (len, mtime_secs, mtime_nanos) = Self::metadata(&file)?;
+        Ok(Self {
+            len,
+            mtime_secs,
+            mtime_nanos,
+            checksum: Self::checksum(&file)?,
+        })
+    }
+
+    /// Check whether a file still matches the stamp
+    /// 
+    /// Size and modification time are compared first; the checksum is only
+    /// recomputed if the file was touched without changing its size
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to a file
+    /// 
+    /// # Returns
+    /// 
+    /// A `Result` containing `true` if the file matches the stamp
+    /// 
+    pub fn matches<U>(&self, file: U) -> Result<bool>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let (len, mtime_secs, mtime_nanos) = Self::metadata(&file)?;
+        if len != self.len {
+            return Ok(false);
+        }
+        if mtime_secs == self.mtime_secs && mtime_nanos == self.mtime_nanos {
+            return Ok(true);
+        }
+        Ok(Self::checksum(&file)? == self.checksum)
+    }
+
+    // Private getter for file size and modification time
+    fn metadata<U>(file: U) -> Result<(u64, u64, u32)>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let stat = std::fs::metadata(&file)
+            .with_context(|| format!("Failed to get metadata for {:?}", file))?;
+        let mtime = stat
+            .modified()
+            .ok()
+            .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Ok((stat.len(), mtime.as_secs(), mtime.subsec_nanos()))
+    }
 
-        // serialize the results and exit
-        // Writer::to_bin(results, out_file)
-        // f = File::write(out_file);
+    // Private CRC32 calculator streaming over the raw file bytes
+    fn checksum<U>(file: U) -> Result<u32>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let mut f = BufReader::new(
+            File::open(&file).with_context(|| format!("Failed to open file {:?}", file))?
+        );
+        let mut crc = flate2::Crc::new();
+        let mut buf = vec![0u8; 1 << 16];
+        loop {
+            let n = f.read(&mut buf).with_context(|| format!("Failed to read file {:?}", file))?;
+            if n == 0 {break}
+            crc.update(&buf[..n]);
+        }
+        Ok(crc.sum())
+    }
+}
 
+/// Contents of a chain index file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainIndex {
+    /// Stamp of the chain file the index was built for
+    pub source: SourceStamp,
+    /// Whether the chain file is BGZF-compressed
+    pub bgzf: bool,
+    /// {chain_id: (first_byte, last_byte)} map; offsets are BGZF virtual offsets
+    /// (`block start << 16 | offset within block`) for compressed files
+    pub offsets: ChainOffsets,
+    /// Reference and query spans of indexed chains
+    pub regions: RegionIndex,
+}
 
+impl ChainIndex {
+    /// Save the index to a file, prefixed with the magic bytes and format version
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to the output file
+    /// 
+    /// # Returns
+    /// 
+    /// * Result<()>
+    /// 
+    pub fn write<U>(&self, file: U) -> Result<()>
+    where
+        U: AsRef<Path> + Debug,
+    {
+        let f = File::create(&file).with_context(|| format!("Failed to create file {:?}", file))?;
+        let mut out = BufWriter::new(f);
+        out.write_all(INDEX_MAGIC)?;
+        out.write_all(&INDEX_VERSION.to_le_bytes())?;
+        bincode::serialize_into(&mut out, self)
+            .with_context(|| format!("Failed to write index to {:?}", file))?;
+        out.flush()?;
         Ok(())
     }
 
-    /// Reads chain file index
+    /// Load an index from a file, checking the magic bytes and format version
     /// 
     /// # Arguments
     /// 
-    /// * `index_file` - A path to an index file
+    /// * `file` - A path to an index file
     /// 
     /// # Returns
     /// 
-    /// A {chain_id: (first_byte, last_byte)} FxHashMap
+    /// * Result<ChainIndex>
     /// 
-    pub fn read_index<U>(index_file: U) -> Result<FxHashMap<u64, (usize, usize)>>
+    pub fn read<U>(file: U) -> Result<Self>
     where
-        U: AsRef<Path> + Debug + Display
+        U: AsRef<Path> + Debug,
     {
-        let data = Self::open(index_file)?;
-        let index: FxHashMap<u64, (usize, usize)> = bincode::deserialize(&data).with_context(|| 
-            "Deserialization failed"
-        )?;
+        let path = file.as_ref();
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let mut f = BufReader::new(f);
+        let mut magic = [0u8; 8];
+        let mut version = [0u8; 2];
+        if f.read_exact(&mut magic).is_err() || &magic != INDEX_MAGIC {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: path.into(),
+                message: String::from(
+                    "Not a chaintools index file or created by an older version; rebuild it with BinaryIndex::index",
+                ),
+            }.into());
+        }
+        if f.read_exact(&mut version).is_err() {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: path.into(),
+                message: String::from("Truncated index file"),
+            }.into());
+        }
+        let version = u16::from_le_bytes(version);
+        if version != INDEX_VERSION {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: path.into(),
+                message: format!(
+                    "Index file has version {}, expected {}; rebuild it with BinaryIndex::index",
+                    version, INDEX_VERSION
+                ),
+            }.into());
+        }
+        let index: ChainIndex = bincode::deserialize_from(f)
+            .with_context(|| format!("Failed to read index from {:?}", file))?;
         Ok(index)
     }
 }
+
+/// UCSC-style bin offsets for the standard (up to 512 Mb) binning scheme
+const BIN_OFFSETS: [u32; 5] = [512 + 64 + 8 + 1, 64 + 8 + 1, 8 + 1, 1, 0];
+/// UCSC-style bin offsets for the extended (up to 2 Gb and beyond) binning scheme
+const BIN_OFFSETS_EXTENDED: [u32; 6] = [4096 + 512 + 64 + 8 + 1, 512 + 64 + 8 + 1, 64 + 8 + 1, 8 + 1, 1, 0];
+/// First extended bin number; keeps extended bins apart from the standard ones
+const BIN_OFFSET_OLD_TO_EXTENDED: u32 = 4681;
+/// Maximum coordinate covered by the standard binning scheme
+const BIN_STANDARD_MAX: u64 = 1 << 29;
+/// Shift for the smallest (128 kb) bins
+const BIN_FIRST_SHIFT: u32 = 17;
+/// Shift between the consecutive bin levels
+const BIN_NEXT_SHIFT: u32 = 3;
+
+/// Get the smallest UCSC bin fully containing the [start, end) range
+///
+/// # Arguments
+/// * `start` - A 0-based start coordinate
+/// * `end` - A 0-based end coordinate (exclusive)
+///
+/// # Returns
+/// * u32
+///
+/// # Example
+/// ```
+/// use chaintools::io::indexer::bin_from_range;
+///
+/// assert_eq!(bin_from_range(0, 100), 585);
+/// assert_eq!(bin_from_range(0, 1 << 20), 73);
+/// ```
+pub fn bin_from_range(start: u64, end: u64) -> u32 {
+    let end = end.max(start + 1);
+    let (offsets, base): (&[u32], u32) = if end <= BIN_STANDARD_MAX {
+        (&BIN_OFFSETS, 0)
+    } else {
+        (&BIN_OFFSETS_EXTENDED, BIN_OFFSET_OLD_TO_EXTENDED)
+    };
+    let mut start_bin = start >> BIN_FIRST_SHIFT;
+    let mut end_bin = (end - 1) >> BIN_FIRST_SHIFT;
+    for offset in offsets {
+        if start_bin == end_bin {
+            return base + offset + start_bin as u32;
+        }
+        start_bin >>= BIN_NEXT_SHIFT;
+        end_bin >>= BIN_NEXT_SHIFT;
+    }
+    // the range spans several top-level bins; lookups always include the first one
+    base
+}
+
+/// Get all UCSC bins which can contain features overlapping the [start, end) range
+///
+/// # Arguments
+/// * `start` - A 0-based start coordinate
+/// * `end` - A 0-based end coordinate (exclusive)
+///
+/// # Returns
+/// * Vec<u32>
+fn overlapping_bins(start: u64, end: u64) -> Vec<u32> {
+    let end = end.max(start + 1);
+    let mut bins: Vec<u32> = Vec::new();
+    // standard bins can only hold features ending before the standard scheme limit
+    if start < BIN_STANDARD_MAX {
+        let mut start_bin = start >> BIN_FIRST_SHIFT;
+        let mut end_bin = (end.min(BIN_STANDARD_MAX) - 1) >> BIN_FIRST_SHIFT;
+        for offset in BIN_OFFSETS {
+            for bin in start_bin..=end_bin {
+                bins.push(offset + bin as u32);
+            }
+            start_bin >>= BIN_NEXT_SHIFT;
+            end_bin >>= BIN_NEXT_SHIFT;
+        }
+    }
+    // extended bins hold features ending beyond it, which can still
+    // overlap a region lying entirely within the standard scheme
+    let mut start_bin = start >> BIN_FIRST_SHIFT;
+    let mut end_bin = (end - 1) >> BIN_FIRST_SHIFT;
+    for offset in BIN_OFFSETS_EXTENDED {
+        for bin in start_bin..=end_bin {
+            bins.push(BIN_OFFSET_OLD_TO_EXTENDED + offset + bin as u32);
+        }
+        start_bin >>= BIN_NEXT_SHIFT;
+        end_bin >>= BIN_NEXT_SHIFT;
+    }
+    // features spanning several top-level bins are all kept in the first one
+    if !bins.contains(&BIN_OFFSET_OLD_TO_EXTENDED) {
+        bins.push(BIN_OFFSET_OLD_TO_EXTENDED);
+    }
+    bins
+}
+
+/// A chain span recorded in a region index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionEntry {
+    pub id: ChainId,
+    pub start: u64,
+    pub end: u64,
+}
+
+/// UCSC-style bins of chain spans for one side of the alignment
+///
+/// Spans are recorded on the positive strand regardless of the chain's strand,
+/// so lookups always use forward-strand genomic coordinates.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpanBins {
+    pub bins: FxHashMap<String, FxHashMap<u32, Vec<RegionEntry>>>,
+}
+
+impl SpanBins {
+    /// Record a chain span
+    ///
+    /// # Arguments
+    /// * `chrom` - A chromosome name
+    /// * `start` - A 0-based forward-strand start coordinate
+    /// * `end` - A 0-based forward-strand end coordinate (exclusive)
+    /// * `id` - A chain ID
+    pub fn insert(&mut self, chrom: &str, start: u64, end: u64, id: ChainId) {
+        let bin = bin_from_range(start, end);
+        if !self.bins.contains_key(chrom) {
+            self.bins.insert(chrom.to_string(), FxHashMap::default());
+        }
+        self.bins
+            .get_mut(chrom)
+            .unwrap()
+            .entry(bin)
+            .or_default()
+            .push(RegionEntry { id, start, end });
+    }
+
+    /// Record a chain span from a chain head, converting negative strand
+    /// coordinates (`size - end`..`size - start`) to the positive strand
+    ///
+    /// # Arguments
+    /// * `head` - A chain head object
+    /// * `id` - A chain ID
+    pub fn insert_head(&mut self, head: &ChainHead, id: ChainId) {
+        let (start, end) = match head.strand {
+            '-' => (head.size.saturating_sub(head.end), head.size.saturating_sub(head.start)),
+            _ => (head.start, head.end),
+        };
+        self.insert(&head.chr, start, end, id);
+    }
+
+    /// Get IDs of all chains overlapping a region; IDs might repeat
+    /// if a chain was recorded more than once
+    ///
+    /// # Arguments
+    /// * `chrom` - A chromosome name
+    /// * `start` - A 0-based start coordinate
+    /// * `end` - A 0-based end coordinate (exclusive)
+    ///
+    /// # Returns
+    /// * Iterator<Item = ChainId>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::indexer::SpanBins;
+    ///
+    /// let mut bins = SpanBins::default();
+    /// // a span across a 4 Gb boundary fits no single bin
+    /// bins.insert("chr1", (5 << 32) - 10, (5 << 32) + 10, 1);
+    /// assert_eq!(bins.overlapping("chr1", 5 << 32, (5 << 32) + 1).collect::<Vec<_>>(), vec![1]);
+    /// ```
+    pub fn overlapping(&self, chrom: &str, start: u64, end: u64) -> impl Iterator<Item = ChainId> + '_ {
+        let bins = self.bins.get(chrom);
+        overlapping_bins(start, end)
+            .into_iter()
+            .filter_map(move |bin| bins.and_then(|x| x.get(&bin)))
+            .flatten()
+            .filter(move |entry| entry.start < end && entry.end > start)
+            .map(|entry| entry.id)
+    }
+}
+
+/// A UCSC-style binned index of chain spans in both reference and query coordinates
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RegionIndex {
+    pub refs: SpanBins,
+    pub query: SpanBins,
+}
+
+impl RegionIndex {
+    /// Create a new empty region index
+    ///
+    /// # Returns
+    /// * RegionIndex
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a region index for all chains in a ChainMap
+    ///
+    /// # Arguments
+    /// * `chains` - A ChainMap
+    ///
+    /// # Returns
+    /// * RegionIndex
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::chain::BlockSide;
+    /// use chaintools::io::indexer::RegionIndex;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let chains = Reader::from_bytes(line).unwrap();
+    /// let index = RegionIndex::from_chains(&chains);
+    ///
+    /// // the query span lies on the negative strand: 151006098 - 43257528 .. 151006098 - 43257292
+    /// assert_eq!(index.fetch(BlockSide::Query, "chr5", 107748570, 107748571), vec![1]);
+    /// assert!(index.fetch(BlockSide::Query, "chr5", 43257292, 43257528).is_empty());
+    /// ```
+    pub fn from_chains(chains: &ChainMap) -> Self {
+        let mut index = Self::new();
+        for (id, chain) in chains.iter() {
+            index.insert_chain(chain, *id);
+        }
+        index
+    }
+
+    /// Record both spans of a chain
+    ///
+    /// # Arguments
+    /// * `chain` - A chain object
+    /// * `id` - A chain ID
+    pub fn insert_chain(&mut self, chain: &Chain, id: ChainId) {
+        self.insert_heads(&chain.refs, &chain.query, id);
+    }
+
+    /// Record both spans of a chain from its heads
+    ///
+    /// # Arguments
+    /// * `refs` - A reference chain head
+    /// * `query` - A query chain head
+    /// * `id` - A chain ID
+    pub fn insert_heads(&mut self, refs: &ChainHead, query: &ChainHead, id: ChainId) {
+        self.refs.insert_head(refs, id);
+        self.query.insert_head(query, id);
+    }
+
+    /// Get IDs of all chains overlapping a region
+    ///
+    /// # Arguments
+    /// * `side` - Assembly to look the region up in; `BlockSide::Both` matches either of them
+    /// * `chrom` - A chromosome name
+    /// * `start` - A 0-based forward-strand start coordinate
+    /// * `end` - A 0-based forward-strand end coordinate (exclusive)
+    ///
+    /// # Returns
+    /// * Vec<ChainId> - chain IDs sorted in ascending order
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::chain::BlockSide;
+    /// use chaintools::io::indexer::RegionIndex;
+    ///
+    /// let mut index = RegionIndex::new();
+    /// index.refs.insert("chr15", 40_000_000, 41_000_000, 38);
+    /// index.refs.insert("chr15", 42_000_000, 43_000_000, 39);
+    ///
+    /// assert_eq!(index.fetch(BlockSide::Ref, "chr15", 40_500_000, 40_600_000), vec![38]);
+    /// ```
+    pub fn fetch(&self, side: BlockSide, chrom: &str, start: u64, end: u64) -> Vec<ChainId> {
+        let mut ids: Vec<ChainId> = match side {
+            BlockSide::Ref => self.refs.overlapping(chrom, start, end).collect(),
+            BlockSide::Query => self.query.overlapping(chrom, start, end).collect(),
+            BlockSide::Both => self
+                .refs
+                .overlapping(chrom, start, end)
+                .chain(self.query.overlapping(chrom, start, end))
+                .collect(),
+        };
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    /// Get all chains from a ChainMap overlapping a region
+    ///
+    /// # Arguments
+    /// * `chains` - The ChainMap the index was built for
+    /// * `side` - Assembly to look the region up in; `BlockSide::Both` matches either of them
+    /// * `chrom` - A chromosome name
+    /// * `start` - A 0-based forward-strand start coordinate
+    /// * `end` - A 0-based forward-strand end coordinate (exclusive)
+    ///
+    /// # Returns
+    /// * Vec<&Chain> - chains sorted by ascending ID
+    pub fn fetch_chains<'a>(
+        &self,
+        chains: &'a ChainMap,
+        side: BlockSide,
+        chrom: &str,
+        start: u64,
+        end: u64,
+    ) -> Vec<&'a Chain> {
+        self.fetch(side, chrom, start, end)
+            .iter()
+            .filter_map(|id| chains.get(id))
+            .collect()
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt::{self, Display};
+
+use crate::cmap::chain::ChainId;
+use crate::cmap::map::MergePolicy;
+use crate::error::ChaintoolsError;
+
+/// How chain parsers deal with malformed chains
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Fail on the first malformed chain
+    #[default]
+    Strict,
+    /// Skip malformed chains and report them
+    Lenient,
+    /// Fix recoverable defects, skip and report the rest
+    Repair,
+}
+
+/// Options controlling how chain files are parsed
+///
+/// Used by [`crate::io::reader::Reader::from_file_with`], [`crate::io::reader::Reader::from_bytes_with`],
+/// [`crate::io::reader::Reader::extract_with`] and [`crate::io::reader::Reader::extract_ix_with`];
+/// the plain variants of these functions parse in [`ParseMode::Strict`] mode and keep the
+/// first of chains sharing an ID ([`MergePolicy::PreferLeft`]), reporting the others as skipped.
+///
+/// In [`ParseMode::Repair`] mode the following defects are fixed:
+/// * missing chain IDs, replaced by IDs following the largest one in the file
+/// * tabs, repeated spaces or carriage returns between fields
+/// * a trailing `dt`/`dq` gap after the final block, which is dropped
+/// * blocks given by their size only before the final block, which get no gaps
+/// * header end coordinates disagreeing with the alignment, recomputed from the blocks
+///   as long as they stay within the sequence sizes
+///
+/// # Example
+/// ```
+/// use chaintools::io::parse::ParseOptions;
+/// use chaintools::io::reader::Reader;
+///
+/// let data = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n\
+/// chain 1000 chr1 1000000 + 100 300 chrA 5000 + 1000 1210 2\n50\t10\n140\n";
+///
+/// assert!(Reader::from_bytes(data).is_err());
+///
+/// let (chains, report) = Reader::from_bytes_with(data, &ParseOptions::lenient()).unwrap();
+/// assert_eq!(chains.len(), 1);
+/// assert_eq!(report.skipped().count(), 1);
+/// assert_eq!(report.issues[0].chain_id, Some(2));
+/// assert_eq!(report.issues[0].line, Some(6));
+///
+/// // chains without blocks or without a final block of size only are malformed as well
+/// let empty = b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1200 2\n\n";
+/// assert!(Reader::from_bytes(empty).is_err());
+/// let (chains, report) = Reader::from_bytes_with(empty, &ParseOptions::lenient()).unwrap();
+/// assert_eq!((chains.len(), report.skipped().count()), (0, 1));
+/// assert!(Reader::from_bytes(b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1200 2\n50\t10\t0\n140\t0\t0\n").is_err());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+    /// How chains sharing an ID are resolved; the left chain is the first one in file order
+    pub duplicates: MergePolicy,
+}
+
+impl ParseOptions {
+    /// Create parse options for a given mode
+    ///
+    /// # Arguments
+    /// * `mode` - A parse mode
+    ///
+    /// # Returns
+    /// * ParseOptions
+    pub fn new(mode: ParseMode) -> Self {
+        Self { mode, duplicates: MergePolicy::default() }
+    }
+
+    /// Set the policy for chains sharing an ID
+    ///
+    /// Chain files concatenated from different runs routinely reuse IDs; whatever the
+    /// policy, the outcome never depends on the order in which chains are parsed.
+    ///
+    /// # Arguments
+    /// * `policy` - A merge policy, applied to chains in file order
+    ///
+    /// # Returns
+    /// * ParseOptions
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::MergePolicy;
+    /// use chaintools::io::parse::ParseOptions;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = b"chain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\nchain 20 chr1 100 + 50 60 chr2 100 + 50 60 1\n10\n\n";
+    /// assert_eq!(Reader::from_bytes(data).unwrap().get(&1).unwrap().score, 10);
+    ///
+    /// let (_, report) = Reader::from_bytes_with(data, &ParseOptions::strict()).unwrap();
+    /// assert_eq!(report.skipped().count(), 1);
+    /// assert!(Reader::from_bytes_with(data, &ParseOptions::strict().with_duplicates(MergePolicy::Error)).is_err());
+    ///
+    /// let options = ParseOptions::strict().with_duplicates(MergePolicy::Renumber);
+    /// let (chains, report) = Reader::from_bytes_with(data, &options).unwrap();
+    /// assert_eq!(chains.len(), 2);
+    /// assert_eq!(report.id_map, vec![(1, 2)]);
+    /// assert_eq!(chains.get(&2).unwrap().score, 20);
+    /// ```
+    pub fn with_duplicates(mut self, policy: MergePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Options failing on the first malformed chain (the default)
+    ///
+    /// # Returns
+    /// * ParseOptions
+    pub fn strict() -> Self {
+        Self::new(ParseMode::Strict)
+    }
+
+    /// Options skipping malformed chains
+    ///
+    /// # Returns
+    /// * ParseOptions
+    pub fn lenient() -> Self {
+        Self::new(ParseMode::Lenient)
+    }
+
+    /// Options repairing malformed chains where possible
+    ///
+    /// # Returns
+    /// * ParseOptions
+    pub fn repair() -> Self {
+        Self::new(ParseMode::Repair)
+    }
+}
+
+/// What happened to a chain listed in a [`ParseReport`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseAction {
+    /// The chain was left out of the result
+    Skipped,
+    /// The chain was fixed and kept
+    Repaired,
+    /// The chain shared its ID with another chain and was given a new one
+    Renumbered,
+}
+
+/// A chain skipped, repaired or renumbered while parsing
+///
+/// Line numbers are 1-based and byte offsets 0-based, pointing to the offending
+/// line for skipped chains and to the chain header for the others.
+#[derive(Debug, Clone)]
+pub struct ParseIssue {
+    pub chain_id: Option<ChainId>,
+    pub line: Option<u64>,
+    pub offset: Option<u64>,
+    pub action: ParseAction,
+    pub reason: String,
+}
+
+impl ParseIssue {
+    /// Create a skipped-chain issue from a parsing error
+    ///
+    /// # Arguments
+    /// * `err` - A parsing error, located in the input
+    ///
+    /// # Returns
+    /// * ParseIssue
+    pub(crate) fn skipped(err: &anyhow::Error) -> Self {
+        let (chain_id, line, offset) = match err.downcast_ref::<ChaintoolsError>() {
+            Some(e) => (e.chain_id(), e.line(), e.offset()),
+            None => (None, None, None),
+        };
+        Self {
+            chain_id,
+            line,
+            offset,
+            action: ParseAction::Skipped,
+            reason: err.to_string(),
+        }
+    }
+}
+
+impl Display for ParseIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self.action {
+            ParseAction::Skipped => return write!(f, "skipped: {}", self.reason),
+            ParseAction::Repaired => "repaired",
+            ParseAction::Renumbered => "renumbered",
+        };
+        write!(f, "{}", action)?;
+        if let Some(id) = self.chain_id {
+            write!(f, " chain {}", id)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " at line {}", line)?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+/// Diagnostics collected while parsing a chain file
+///
+/// Lists every chain that was skipped, repaired or renumbered, in file order;
+/// a report from a [`ParseMode::Strict`] parse with [`MergePolicy::Error`] is
+/// always clean. `id_map` maps the original ID of every renumbered chain
+/// to its new ID, in file order; an original ID repeats if it was shared by more
+/// than two chains.
+#[derive(Debug, Clone, Default)]
+pub struct ParseReport {
+    pub issues: Vec<ParseIssue>,
+    pub id_map: Vec<(ChainId, ChainId)>,
+}
+
+impl ParseReport {
+    /// Check whether all chains were parsed as they are
+    ///
+    /// # Returns
+    /// * bool
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Iterate over the skipped chains
+    ///
+    /// # Returns
+    /// * An iterator of ParseIssue
+    pub fn skipped(&self) -> impl Iterator<Item = &ParseIssue> {
+        self.issues.iter().filter(|x| x.action == ParseAction::Skipped)
+    }
+
+    /// Iterate over the repaired chains
+    ///
+    /// # Returns
+    /// * An iterator of ParseIssue
+    pub fn repaired(&self) -> impl Iterator<Item = &ParseIssue> {
+        self.issues.iter().filter(|x| x.action == ParseAction::Repaired)
+    }
+
+    /// Iterate over the renumbered chains
+    ///
+    /// # Returns
+    /// * An iterator of ParseIssue
+    pub fn renumbered(&self) -> impl Iterator<Item = &ParseIssue> {
+        self.issues.iter().filter(|x| x.action == ParseAction::Renumbered)
+    }
+}
+
+impl Display for ParseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} chains skipped, {} chains repaired, {} chains renumbered",
+            self.skipped().count(),
+            self.repaired().count(),
+            self.renumbered().count()
+        )?;
+        for issue in &self.issues {
+            write!(f, "\n{}", issue)?;
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,26 @@
 use anyhow::{Context, Result};
-use flate2::read::MultiGzDecoder;
-use fxhash::FxHashMap;
-use memchr::{memchr, memrchr};
+use fxhash::{FxHashMap, FxHashSet};
+use memchr::{memchr, memchr_iter, memmem};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 // use std::borrow::Cow;
 // use std::ops::RangeBounds;
-use std::{fmt::{Debug, Display}, fs::File, io::{BufRead, BufReader, Read, Seek, SeekFrom}, path::Path};
+use std::{fmt::{Debug, Display}, fs::File, io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom}, path::Path};
 
-use crate::cmap::chain::Chain;
-use crate::cmap::map::ChainMap;
-// use crate::io::indexer::BinaryIndex;
+use crate::cmap::chain::{BlockSide, Chain, ChainHeader, ChainId};
+use crate::cmap::map::{ChainMap, MergePolicy, Metadata, Provenance};
+use crate::error::ChaintoolsError;
+use crate::io::bgzf::BgzfReader;
+use crate::io::codec::{decode_chain, decode_table};
+use crate::io::compress::Compression;
+use crate::io::indexer::{BinaryIndex, ChainIndex, ChainOffsets};
+use crate::io::parse::{ParseAction, ParseIssue, ParseMode, ParseOptions, ParseReport};
+use crate::io::writer::{BIN_HEADER_SIZE, BIN_MAGIC, BIN_VERSION};
+
+/// A seekable byte source
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
 
 /// A reader for chain files.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +38,9 @@ impl Reader {
     /// A `Result` containing a `FxHashMap` of `Chain` objects.
     /// will be ignored
     ///
+    /// Of chains sharing an ID only the first one in the file is kept; use
+    /// [`Reader::from_file_with`] to list the others or to choose another [`MergePolicy`].
+    ///
     /// # Example
     ///
     /// ```
@@ -43,6 +56,37 @@ impl Reader {
         Self::parse(&data)
     }
 
+    /// Create a new reader with explicit parse options.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - A path to a chain file.
+    /// * `options` - Parse options deciding what happens to malformed chains.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `ChainMap` and a `ParseReport` listing every
+    /// skipped or repaired chain.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::parse::ParseOptions;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let (chains, report) = Reader::from_file_with("/path/to/chainfile", &ParseOptions::lenient()).unwrap();
+    /// if !report.is_clean() {
+    ///     eprintln!("{}", report);
+    /// }
+    /// ```
+    pub fn from_file_with<T>(file: T, options: &ParseOptions) -> Result<(ChainMap, ParseReport)>
+    where
+        T: AsRef<Path> + Debug,
+    {
+        let data = Self::open(file)?;
+        Self::from_bytes_with(&data, options)
+    }
+
     /// Parser for chain files.
     ///
     /// # Arguments
@@ -60,46 +104,219 @@ impl Reader {
     /// let data = chain::Reader::parse(&data)?;
     /// ```
     pub fn parse(data: &[u8]) -> Result<ChainMap> {
-        let mut vacc: Vec<(&[u8], &[u8])> = Vec::new();
-        let mut data = &data[..];
-        loop {
-            let sep = memchr(b'\n', &data).with_context(|| {
-                format!(
-                    "Failed to find separator in: {:?}. Bad formatted line!",
-                    String::from_utf8_lossy(data)
-                )
-            })?;
-            let Some(end) = memchr(b'c', &data[sep..]) else {
-                let header = &data[..sep];
-                let block = &data[sep + 1..];
-                vacc.push((header, block));
-                break;
-            };
-            let header = &data[..sep];
-            let block = &data[sep + 1..sep + end - 1];
-            vacc.push((header, block));
-            data = &data[sep + end..];
-        }
+        Self::from_bytes_with(data, &ParseOptions::default()).map(|(chains, _)| chains)
+    }
 
-        let chainfile = vacc
+    /// Create a new reader parsing only chain headers; alignment blocks
+    /// are kept as raw text and parsed on first access.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - A path to a chain file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `ChainMap` of lazily parsed `Chain` objects.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = Reader::from_file_lazy("/path/to/chainfile").unwrap();
+    /// // header-only filters never parse the alignment blocks
+    /// let best = data.filter_by_score(100_000);
+    /// ```
+    pub fn from_file_lazy<T>(file: T) -> Result<ChainMap>
+    where
+        T: AsRef<Path> + Debug,
+    {
+        let data = Self::open(file)?;
+        Self::parse_lazy(&data)
+    }
+
+    /// Parser for chain files deferring alignment parsing until first access.
+    ///
+    /// # Arguments
+    /// * `data` - A reference to a byte slice.
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of lazily parsed `Chain` objects.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let data = Reader::parse_lazy(line).unwrap();
+    /// let chain = data.get(&1).unwrap();
+    ///
+    /// assert!(!chain.alignment.is_parsed());
+    /// assert_eq!(chain.alignment.len(), 2);
+    ///
+    /// // malformed blocks fail on first access, at the same position as when read eagerly
+    /// let bad = b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1210 1\n50\tx\t20\n140\n\n";
+    /// let chains = Reader::parse_lazy(bad).unwrap();
+    /// let err = chains.get(&1).unwrap().alignment.try_records().unwrap_err();
+    /// assert_eq!(err.to_string(), Reader::from_bytes(bad).unwrap_err().to_string());
+    /// assert!(err.to_string().contains("line 2 (byte 54) in chain 1"));
+    /// ```
+    pub fn parse_lazy(data: &[u8]) -> Result<ChainMap> {
+        let vacc = Self::split(data)?;
+        let (mut chains, _) = Self::collect(data, &vacc, MergePolicy::default(), |records, record| {
+            let (line, offset) = records.block_position(record);
+            Chain::from_lazy(record.header, record.block)
+                .map(|(id, mut chain)| {
+                    chain.alignment.locate(line, offset, id);
+                    Outcome::Parsed(id, chain, records.position(record))
+                })
+                .map_err(|e| records.locate(e, record))
+        })?;
+        chains.metadata = Metadata::from_bytes(data);
+        Ok(chains)
+    }
+
+    // Private splitter of chain files into records
+    fn split(data: &[u8]) -> Result<Vec<Record<'_>>> {
+        Ok(Records::new(data).collect())
+    }
+
+    // Private parallel builder of a ChainMap from records;
+    // fails with the first error in file order
+    fn collect<F>(data: &[u8], vacc: &[Record<'_>], policy: MergePolicy, build: F) -> Result<(ChainMap, ParseReport)>
+    where
+        F: Fn(&Records<'_>, &Record<'_>) -> Result<Outcome> + Sync,
+    {
+        let records = Records::new(data);
+        let outcomes: Vec<Result<Outcome>> = vacc
             .par_iter()
-            .filter_map(|(header, block)| Chain::from(header, block).ok())
-            .fold(
-                || FxHashMap::default(),
-                |mut acc, chain| {
-                    acc.insert(chain.0, chain.1);
-                    acc
-                },
-            )
-            .reduce(
-                || FxHashMap::default(),
-                |mut acc, map| {
-                    acc.extend(map);
-                    acc
-                },
-            );
-
-        Ok(ChainMap { map: chainfile })
+            .map(|record| build(&records, record))
+            .collect();
+        let outcomes = outcomes.into_iter().collect::<Result<Vec<Outcome>>>()?;
+        Self::assemble(outcomes, policy, None)
+    }
+
+    // Private parser of a single record honoring the parse mode
+    fn build_record(records: &Records<'_>, record: &Record<'_>, options: &ParseOptions) -> Result<Outcome> {
+        let err = match Chain::from(record.header, record.block) {
+            Ok((id, chain)) => return Ok(Outcome::Parsed(id, chain, records.position(record))),
+            Err(e) => records.locate(e, record),
+        };
+        match options.mode {
+            ParseMode::Strict => Err(err),
+            ParseMode::Lenient => Ok(Outcome::Skipped(ParseIssue::skipped(&err))),
+            ParseMode::Repair => match Chain::repair(record.header, record.block) {
+                Ok((id, chain, notes)) => {
+                    let (line, offset) = records.position(record);
+                    Ok(Outcome::Repaired(id, chain, ParseIssue {
+                        chain_id: id,
+                        line,
+                        offset,
+                        action: ParseAction::Repaired,
+                        reason: notes.join("; "),
+                    }))
+                }
+                Err(_) => Ok(Outcome::Skipped(ParseIssue::skipped(&err))),
+            },
+        }
+    }
+
+    // Private assembler of parsed records into a ChainMap and a report, in file order;
+    // repaired chains without an ID and renumbered duplicates get IDs following the
+    // largest one, in the parsed records or in `largest` (the largest ID in the file)
+    fn assemble(outcomes: Vec<Outcome>, policy: MergePolicy, largest: Option<ChainId>) -> Result<(ChainMap, ParseReport)> {
+        let mut next_id: ChainId = outcomes
+            .iter()
+            .filter_map(|x| match x {
+                Outcome::Parsed(id, _, _) | Outcome::Repaired(Some(id), _, _) => Some(*id),
+                _ => None,
+            })
+            .chain(largest)
+            .max()
+            .map_or(1, |x| x.saturating_add(1));
+
+        let mut chainfile: FxHashMap<ChainId, Chain> = FxHashMap::default();
+        chainfile.reserve(outcomes.len());
+        // header positions of the kept chains, to report the ones dropped later on
+        let mut kept_at: FxHashMap<ChainId, (Option<u64>, Option<u64>)> = FxHashMap::default();
+        let mut report = ParseReport::default();
+        for outcome in outcomes {
+            let (id, mut chain, (line, offset), issue) = match outcome {
+                Outcome::Parsed(id, chain, position) => (id, chain, position, None),
+                Outcome::Repaired(Some(id), chain, issue) => (id, chain, (issue.line, issue.offset), Some(issue)),
+                Outcome::Repaired(None, mut chain, mut issue) => {
+                    let id = next_id;
+                    next_id = next_id.saturating_add(1);
+                    let note = format!("assigned ID {} to a chain without ID", id);
+                    issue.reason = if issue.reason.is_empty() { note } else { format!("{}; {}", note, issue.reason) };
+                    issue.chain_id = Some(id);
+                    chain.id = id;
+                    chainfile.insert(id, chain);
+                    report.issues.push(issue);
+                    continue;
+                }
+                Outcome::Skipped(issue) => {
+                    report.issues.push(issue);
+                    continue;
+                }
+            };
+            let duplicate = |(line, offset): (Option<u64>, Option<u64>), kept: String| {
+                let at = line.map(|x| format!(" at line {}", x)).unwrap_or_default();
+                ParseIssue {
+                    chain_id: Some(id),
+                    line,
+                    offset,
+                    action: ParseAction::Skipped,
+                    reason: format!("duplicate chain ID {}{}, kept {}", id, at, kept),
+                }
+            };
+            let Some(first) = chainfile.get(&id) else {
+                chain.id = id;
+                chainfile.insert(id, chain);
+                kept_at.insert(id, (line, offset));
+                report.issues.extend(issue);
+                continue;
+            };
+            match policy {
+                MergePolicy::Error => {
+                    return Err(ChaintoolsError::DuplicateChain { chain_id: id, line, offset }.into());
+                }
+                MergePolicy::PreferLeft => {
+                    report.issues.push(duplicate((line, offset), String::from("the first chain")));
+                }
+                MergePolicy::PreferHigherScore => {
+                    if chain.score > first.score {
+                        let dropped = kept_at.insert(id, (line, offset)).unwrap_or_default();
+                        report.issues.push(duplicate(dropped, format!("the chain with score {}", chain.score)));
+                        chainfile.insert(id, chain);
+                        report.issues.extend(issue);
+                    } else {
+                        report.issues.push(duplicate((line, offset), format!("the chain with score {}", first.score)));
+                    }
+                }
+                MergePolicy::Renumber => {
+                    let new_id = next_id;
+                    next_id = next_id.saturating_add(1);
+                    chain.id = new_id;
+                    chainfile.insert(new_id, chain);
+                    report.issues.extend(issue);
+                    report.issues.push(ParseIssue {
+                        chain_id: Some(new_id),
+                        line,
+                        offset,
+                        action: ParseAction::Renumbered,
+                        reason: format!("duplicate chain ID {}, assigned ID {}", id, new_id),
+                    });
+                    report.id_map.push((id, new_id));
+                }
+            }
+        }
+        if policy == MergePolicy::PreferHigherScore {
+            // chains dropped in favour of a later one are reported out of order
+            report.issues.sort_by_key(|x| x.offset);
+        }
+        Ok((ChainMap { map: chainfile, metadata: Metadata::new(), provenance: Provenance::new() }, report))
     }
 
     /// Create a new reader from a byte slice.
@@ -127,32 +344,137 @@ impl Reader {
         Reader::parse(data)
     }
 
-    /// Create a new reader from a binary file.
+    /// Create a new reader from a byte slice with explicit parse options.
     ///
     /// # Arguments
-    /// * `bin` - A path to a binary file.
+    ///
+    /// * `data` - A reference to a byte slice.
+    /// * `options` - Parse options deciding what happens to malformed chains.
     ///
     /// # Returns
-    /// A `Result` containing a `FxHashMap` of `Chain` objects.
+    ///
+    /// A `Result` containing a `ChainMap` and a `ParseReport` listing every
+    /// skipped or repaired chain.
     ///
     /// # Example
     ///
     /// ```
-    /// use chaintools as chain;
+    /// use chaintools::io::parse::ParseOptions;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// // no chain ID and a trailing gap after the final block
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528\n9\t1\t0\n225\t0\t0\n\n";
+    /// let (data, report) = Reader::from_bytes_with(line, &ParseOptions::repair()).unwrap();
+    ///
+    /// assert_eq!(data.len(), 1);
+    /// assert_eq!(report.repaired().count(), 1);
+    /// assert_eq!(report.issues[0].chain_id, Some(1));
+    /// ```
+    pub fn from_bytes_with(data: &[u8], options: &ParseOptions) -> Result<(ChainMap, ParseReport)> {
+        let vacc = Self::split(data)?;
+        let (mut chains, report) = Self::collect(data, &vacc, options.duplicates, |records, record| {
+            Self::build_record(records, record, options)
+        })?;
+        chains.metadata = Metadata::from_bytes(data);
+        Ok((chains, report))
+    }
+
+    /// Create a new reader from any byte source, such as stdin, a pipe or a cursor.
+    ///
+    /// Compressed sources are detected from their magic bytes (see [`Compression`])
+    /// and decompressed on the fly.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - A source of plain or compressed chain data.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `ChainMap`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
     ///
-    /// let data = chain::Reader::from_bin("/path/to/binfile")?;
+    /// // e.g. `zcat hg38ToMm10.over.chain.gz | my_tool`
+    /// let chains = Reader::from_reader(std::io::stdin().lock()).unwrap();
+    /// ```
+    pub fn from_reader<R: Read>(reader: R) -> Result<ChainMap> {
+        Self::from_reader_with(reader, &ParseOptions::default()).map(|(chains, _)| chains)
+    }
+
+    /// Create a new reader from any byte source with explicit parse options.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - A source of plain or compressed chain data.
+    /// * `options` - Parse options deciding what happens to malformed chains.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `ChainMap` and a `ParseReport`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::io::parse::ParseOptions;
+    /// use chaintools::io::reader::Reader;
+    /// use std::io::Cursor;
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let (chains, report) = Reader::from_reader_with(Cursor::new(line), &ParseOptions::lenient()).unwrap();
+    ///
+    /// assert_eq!(chains.len(), 1);
+    /// assert!(report.is_clean());
+    /// ```
+    pub fn from_reader_with<R: Read>(reader: R, options: &ParseOptions) -> Result<(ChainMap, ParseReport)> {
+        let data = Self::read_source(reader, 0, None)?;
+        Self::from_bytes_with(&data, options)
+    }
+
+    /// Create a new reader from a binary container file.
+    ///
+    /// # Arguments
+    /// * `bin` - A path to a binary file written by [`crate::io::writer::Writer::to_bin`].
+    ///
+    /// # Returns
+    /// A `Result` containing a `FxHashMap` of `Chain` objects.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let data = Reader::from_bin("/path/to/binfile").unwrap();
     /// ```
     pub fn from_bin<T>(bin: T) -> Result<ChainMap>
     where
         T: AsRef<Path> + Debug,
     {
-        let data = Self::open(bin)?;
-        let decoded: ChainMap =
-            bincode::deserialize(&data).with_context(|| "Deserialization failed")?;
-        Ok(decoded)
+        let (mut src, table) = Self::open_bin(&bin)?;
+        let mut records: Vec<(u64, u64)> = table.into_values().collect();
+        records.sort_unstable();
+        let records: Vec<Vec<u8>> = records
+            .into_iter()
+            .map(|(offset, len)| Self::read_record(&mut src, offset, len))
+            .collect::<Result<_>>()
+            .with_context(|| format!("Failed to read binary file {:?}", bin))?;
+
+        let chains: Vec<Chain> = records
+            .par_iter()
+            .map(|record| Self::decode_record(record))
+            .collect::<Result<_>>()?;
+        Ok(ChainMap {
+            map: chains.into_iter().map(|chain| (chain.id, chain)).collect(),
+            metadata: Metadata::new(),
+            provenance: Provenance::new(),
+        })
     }
 
-    /// Load a single chain from a binary file.
+    /// Load a single chain from a binary container file.
+    ///
+    /// Only the footer table and the requested chain are decoded.
     ///
     /// # Arguments
     /// * `id` - A chain id.
@@ -163,19 +485,133 @@ impl Reader {
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let chain = Reader::load_chain(123, "/path/to/binfile").unwrap();
     /// ```
-    /// use chaintools as chain;
+    pub fn load_chain<T>(id: ChainId, bin: T) -> Result<Chain>
+    where
+        T: AsRef<Path> + Debug,
+    {
+        let mut chains = Self::load_chains(&[id], &bin)?;
+        chains.map.remove(&id).ok_or_else(|| {
+            ChaintoolsError::MissingChain { chain_id: id, path: Some(bin.as_ref().to_path_buf()) }.into()
+        })
+    }
+
+    /// Load selected chains from a binary container file.
+    ///
+    /// Only the footer table and the requested chains are decoded.
+    ///
+    /// # Arguments
+    /// * `ids` - Chain ids.
+    /// * `bin` - A path to a binary file.
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap`, or an error if any of the chains is missing.
     ///
-    /// let data = chain::Reader::load_chain(&123, "/path/to/binfile")?;
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let chains = Reader::load_chains(&[1, 2, 3], "/path/to/binfile").unwrap();
+    /// assert_eq!(chains.len(), 3);
     /// ```
-    pub fn load_chain<T>(id: u32, bin: T) -> Result<Chain>
+    pub fn load_chains<T>(ids: &[ChainId], bin: T) -> Result<ChainMap>
     where
         T: AsRef<Path> + Debug,
     {
-        let data = Self::open(bin)?;
-        let decoded: ChainMap =
-            bincode::deserialize(&data).with_context(|| "Deserialization failed")?;
-        Ok(decoded.get(&id).expect("Failed to get chain").clone())
+        let (mut src, table) = Self::open_bin(&bin)?;
+        if let Some(missing) = ids.iter().find(|x| !table.contains_key(*x)) {
+            return Err(ChaintoolsError::MissingChain {
+                chain_id: *missing,
+                path: Some(bin.as_ref().to_path_buf()),
+            }.into());
+        }
+
+        let mut chainmap: FxHashMap<ChainId, Chain> = FxHashMap::default();
+        for id in ids {
+            let (offset, len) = table[id];
+            let record = Self::read_record(&mut src, offset, len)?;
+            chainmap.insert(*id, Self::decode_record(&record)?);
+        }
+        Ok(ChainMap { map: chainmap, metadata: Metadata::new(), provenance: Provenance::new() })
+    }
+
+    // Private opener for binary containers
+    //
+    // Checks the container header and trailer and returns the source
+    // along with the footer table; gzipped containers are decompressed in memory
+    fn open_bin<T>(bin: T) -> Result<(Box<dyn ReadSeek>, ChainOffsets)>
+    where
+        T: AsRef<Path> + Debug,
+    {
+        let path = bin.as_ref();
+        let mut f = BufReader::new(File::open(path).map_err(|e| ChaintoolsError::io(path, e))?);
+        let magic = f.fill_buf().map_err(|e| ChaintoolsError::io(path, e))?;
+        // compressed containers cannot be seeked into and are decompressed as a whole
+        let mut src: Box<dyn ReadSeek> = if Compression::detect(magic) != Compression::None {
+            Box::new(Cursor::new(Self::read_source(f, 0, Some(path))?))
+        } else {
+            Box::new(f)
+        };
+
+        let mut magic = [0u8; 8];
+        let mut version = [0u8; 2];
+        if src.read_exact(&mut magic).is_err() || &magic != BIN_MAGIC {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: bin.as_ref().to_path_buf(),
+                message: String::from(
+                    "not a chaintools binary container or created by an older version; re-create it with Writer::to_bin"
+                ),
+            }.into());
+        }
+        src.read_exact(&mut version)
+            .with_context(|| format!("Truncated binary file {:?}", bin))?;
+        let version = u16::from_le_bytes(version);
+        if version != BIN_VERSION {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: bin.as_ref().to_path_buf(),
+                message: format!(
+                    "binary container has version {}, expected {}; re-create it with Writer::to_bin",
+                    version, BIN_VERSION
+                ),
+            }.into());
+        }
+
+        let mut trailer = [0u8; 16];
+        src.seek(SeekFrom::End(-16))
+            .and_then(|_| src.read_exact(&mut trailer))
+            .with_context(|| format!("Truncated binary file {:?}", bin))?;
+        if &trailer[8..] != BIN_MAGIC {
+            return Err(ChaintoolsError::IndexMismatch {
+                path: bin.as_ref().to_path_buf(),
+                message: String::from("binary container is truncated or corrupted"),
+            }.into());
+        }
+        let footer = u64::from_le_bytes(trailer[..8].try_into()?);
+        let end = src.seek(SeekFrom::End(-16))?;
+        let mut encoded = vec![0u8; end.checked_sub(footer).context("Invalid footer offset")? as usize];
+        src.seek(SeekFrom::Start(footer))?;
+        src.read_exact(&mut encoded)?;
+        let table: ChainOffsets = decode_table(&encoded, BIN_HEADER_SIZE, bin.as_ref())
+            .with_context(|| format!("Failed to read the chain table of {:?}", bin))?;
+        Ok((src, table))
+    }
+
+    // Private reader for a single container record
+    fn read_record(src: &mut dyn ReadSeek, offset: u64, len: u64) -> Result<Vec<u8>> {
+        let mut record = vec![0u8; len as usize];
+        src.seek(SeekFrom::Start(offset))?;
+        src.read_exact(&mut record)?;
+        Ok(record)
+    }
+
+    // Private decoder for a single container record
+    fn decode_record(record: &[u8]) -> Result<Chain> {
+        decode_chain(record).with_context(|| "Deserialization failed")
     }
 
     /// Private opener for files.
@@ -197,23 +633,30 @@ impl Reader {
     where
         T: AsRef<Path> + Debug,
     {
-        let stat = std::fs::metadata(&file)
-            .with_context(|| format!("Failed to get metadata for {:?}", file))?;
-        let mut data = vec![];
-        data.reserve(stat.len() as usize + 1);
-
-        let mut f = File::open(&file).with_context(|| format!("Failed to open file {:?}", file))?;
-
-        if file.as_ref().extension().unwrap() == "gz" {
-            let mut decoder = MultiGzDecoder::new(f);
-            decoder
-                .read_to_end(&mut data)
-                .with_context(|| format!("Failed to read file {:?}", file))?;
-        } else {
-            f.read_to_end(&mut data)
-                .with_context(|| format!("Failed to read file {:?}", file))?;
-        }
+        let path = file.as_ref();
+        let stat = std::fs::metadata(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        Self::read_source(f, stat.len() as usize + 1, Some(path))
+    }
 
+    /// Read a whole source into memory, decompressing it if needed.
+    ///
+    /// # Arguments
+    /// * `source` - A source of plain or compressed data.
+    /// * `capacity` - The expected size of the data.
+    /// * `path` - The path of the source, if any, reported in I/O errors.
+    ///
+    /// # Returns
+    /// A `Result` containing a `Vec` of bytes.
+    pub(crate) fn read_source<R: Read>(source: R, capacity: usize, path: Option<&Path>) -> Result<Vec<u8>> {
+        let io = |e: std::io::Error| ChaintoolsError::Io { path: path.map(Path::to_path_buf), source: e };
+        let mut source = BufReader::new(source);
+        // peek first so that read errors are reported before format detection
+        source.fill_buf().map_err(io)?;
+        let mut data = Vec::with_capacity(capacity);
+        Compression::decompress(source)?
+            .read_to_end(&mut data)
+            .map_err(io)?;
         Ok(data)
     }
 
@@ -222,147 +665,712 @@ impl Reader {
     /// # Arguments
     /// * `file` - A path to the chain file 
     /// 
-    /// * `chains` - a vector of string literals containing the chain IDs to extract
-    pub fn extract<U>(file: U, chains: Vec<&str>) -> Result<ChainMap> 
+    /// * `chains` - a vector of chain IDs to extract
+    pub fn extract<U>(file: U, chains: Vec<ChainId>) -> Result<ChainMap> 
     where 
         U: AsRef<Path> + Debug,
         // T:  IntoIterator + RangeBounds<str>,
         // T::IntoIter: ExactSizeIterator
     {
-        let mut chainmap: FxHashMap<u32, Chain> = FxHashMap::default();
+        Self::extract_with(file, chains, &ParseOptions::default()).map(|(chains, _)| chains)
+    }
+
+    /// Extract selected chains from the binary vector with explicit parse options
+    /// 
+    /// # Arguments
+    /// * `file` - A path to the chain file 
+    /// 
+    /// * `chains` - a vector of chain IDs to extract
+    /// 
+    /// * `options` - Parse options deciding what happens to malformed chains
+    /// 
+    /// # Returns
+    /// A `Result` containing a `ChainMap` and a `ParseReport` of the requested chains
+    pub fn extract_with<U>(file: U, chains: Vec<ChainId>, options: &ParseOptions) -> Result<(ChainMap, ParseReport)>
+    where 
+        U: AsRef<Path> + Debug,
+    {
+        let mut outcomes: Vec<Outcome> = Vec::new();
         if chains.len() == 0 {
-            return Ok(ChainMap { map: chainmap });
+            return Self::assemble(outcomes, options.duplicates, None);
         }
+        let chains: FxHashSet<ChainId> = chains.into_iter().collect();
         let data: Vec<u8>  = Self::open(file)?;
-        let mut data = &data[..];
-        loop {
-            let sep = memchr(b'\n', &data).with_context(|| {
-                format!(
-                    "Failed to find separator in: {:?}. Bad formatted line!",
-                    String::from_utf8_lossy(data)
-                )
-            })?;
-
-            let Some(end) = memchr(b'c', &data[sep..]) else {
-                let header = &data[..sep];
-                let id_field: usize = memrchr(b' ', header).with_context(|| {
-                    format!(
-                        "Improperly formatted header line: {:?} !",
-                        String::from_utf8_lossy(header)
-                    )
-                }
-                )?;
-                let id_str = std::str::from_utf8(&header[id_field+1..])?;
-                if chains.contains(&id_str) {
-                    let block = &data[sep + 1..];
-                    let (chain_id, chain_obj) = Chain::from(header, block).unwrap();
-                    chainmap.insert(chain_id, chain_obj);
-                } 
-                break;
-            };
-            let header = &data[..sep];
-            let id_field: usize = memrchr(b' ', header).with_context(|| {
-                format!(
-                    "Improperly formatted header line: {:?} !",
-                    String::from_utf8_lossy(header)
-                )
-            }
-            )?;
-            let id_str: &str = std::str::from_utf8(&header[id_field+1..])?;
+        let records = Records::new(&data);
+        let mut largest: Option<ChainId> = None;
+        for record in Records::new(&data) {
+            let id: Option<ChainId> = record
+                .header
+                .trim_ascii_end()
+                .rsplit(|x| x.is_ascii_whitespace())
+                .next()
+                .and_then(|x| std::str::from_utf8(x).ok())
+                .and_then(|x| x.parse::<ChainId>().ok());
+            largest = largest.max(id);
             // add the chain if its ID is in the requested chains vector
-            if chains.contains(&id_str) {
-                let block = &data[sep + 1..sep + end - 1];
-                let (chain_id, chain_obj) = Chain::from(header, block).unwrap();
-                chainmap.insert(chain_id, chain_obj);
-                // break once all the chains were extracted
-                // TODO: Check for duplicate elements in the chain ID vector??
-                if chainmap.len() == chains.len() {break};
-            } 
-            data = &data[sep + end..];
+            if id.is_some_and(|x| chains.contains(&x)) {
+                outcomes.push(Self::build_record(&records, &record, options)?);
+                // IDs might repeat further down the file unless only the first chain is kept
+                if options.duplicates == MergePolicy::PreferLeft && outcomes.len() == chains.len() {break};
+            }
         }
-        Ok(ChainMap { map: chainmap })
+        let (mut chainmap, report) = Self::assemble(outcomes, options.duplicates, largest)?;
+        chainmap.metadata = Metadata::from_bytes(&data);
+        Ok((chainmap, report))
     }
 
-    // Private function for index reading
-    //
-    // # Arguments
-    // 
-    // * `file` - an index file
-    // * ``
-    fn read_index<U>(file: U, chains: &Vec<u64>, all: bool) -> Result<FxHashMap<u64, (u64, u64)>>
+    /// Extract chains whose headers satisfy a predicate
+    ///
+    /// The predicate sees the score, reference and query heads and the ID of each
+    /// chain before its alignment block is parsed; rejected chains are skipped
+    /// without allocating, as the same header object is reused for the whole file.
+    ///
+    /// # Arguments
+    /// * `file` - A path to the chain file
+    /// * `predicate` - A closure deciding which chains to extract
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of the selected chains
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// // all chains with score > 10000 on chr1
+    /// let chains = Reader::extract_where("/path/to/chainfile", |head| {
+    ///     head.score > 10000 && head.refs.chr == "chr1"
+    /// }).unwrap();
+    ///
+    /// // all chains to a given query scaffold
+    /// let chains = Reader::extract_where("/path/to/chainfile", |head| head.query.chr == "scaffold_12").unwrap();
+    /// ```
+    pub fn extract_where<U, F>(file: U, mut predicate: F) -> Result<ChainMap>
     where
-        U: AsRef<Path> + Debug + Display
+        U: AsRef<Path> + Debug,
+        F: FnMut(&ChainHeader) -> bool,
     {
-        let mut index: FxHashMap<u64, (u64, u64)> = FxHashMap::default();
-        let f = File::open(file)?;
-        let buf = BufReader::new(f).lines();
-        for line in buf.map_while(Result::ok) {
-            let line_data: Vec<u64> = line.split('\t')
-                .map(|x|
-                    x.parse::<u64>().expect("Invalid numeric value found in the index file")
-                )
-                .collect::<Vec<u64>>();
-            if chains.contains(&line_data[0]) || all {
-                index.insert(line_data[0], (line_data[1], line_data[2]));
-            }
-            if index.len() == chains.len() && !all {break}
-        }
-        if index.len() != chains.len() && !all {
-            for c in chains {
-                if !chains.contains(c) {
-                    eprintln!("Chain {} is missing from the chain file", c)
+        let data: Vec<u8> = Self::open(file)?;
+        let mut chainmap: FxHashMap<ChainId, Chain> = FxHashMap::default();
+        let mut head: ChainHeader = ChainHeader::default();
+        let records = Records::new(&data);
+        for record in Records::new(&data) {
+            head.update(record.header).map_err(|e| records.locate(e, &record))?;
+            if predicate(&head) {
+                if chainmap.contains_key(&head.id) {
+                    let (line, offset) = records.position(&record);
+                    return Err(ChaintoolsError::DuplicateChain { chain_id: head.id, line, offset }.into());
                 }
+                let chain = head.to_chain(record.block).map_err(|e| records.locate(e, &record))?;
+                chainmap.insert(head.id, chain);
             }
-            panic!("Certain chains are missing from the chain file");
         }
-        Ok(index)
+        Ok(ChainMap { map: chainmap, metadata: Metadata::from_bytes(&data), provenance: Provenance::new() })
     }
 
     /// Extract selected chains from an indexed file
     /// 
+    /// The index (`<file>.ix`, see [`crate::io::indexer::BinaryIndex::index`]) is checked
+    /// against the chain file first; stale indices are refused instead of returning
+    /// wrong byte ranges. Both plain and BGZF-compressed chain files can be extracted from
+    /// 
     /// # Arguments
     /// 
+    /// * `file` - A path to the chain file
     /// 
+    /// * `chains` - IDs of chains to extract; all chains are extracted if `None`
     /// 
-    pub fn extract_ix<U>(file: U, chains: Option<Vec<u64>>) -> Result<ChainMap>
+    pub fn extract_ix<U>(file: U, chains: Option<Vec<ChainId>>) -> Result<ChainMap>
     where
         U: AsRef<Path> + Debug + Display
     {
-        let mut chainmap: FxHashMap<u32, Chain> = FxHashMap::default();
-        let (chains, all) = match chains {
-            Some(x) => {(x, false)},
-            None => {(Vec::<u64>::new(), true)}
+        Self::extract_ix_with(file, chains, &ParseOptions::default()).map(|(chains, _)| chains)
+    }
+
+    /// Extract selected chains from an indexed file with explicit parse options
+    /// 
+    /// Missing chain IDs and stale indices are still reported as errors in any mode.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file` - A path to the chain file
+    /// 
+    /// * `chains` - IDs of chains to extract; all chains are extracted if `None`
+    /// 
+    /// * `options` - Parse options deciding what happens to malformed chains
+    /// 
+    /// # Returns
+    /// A `Result` containing a `ChainMap` and a `ParseReport` of the requested chains
+    pub fn extract_ix_with<U>(file: U, chains: Option<Vec<ChainId>>, options: &ParseOptions) -> Result<(ChainMap, ParseReport)>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let index: ChainIndex = BinaryIndex::load(&file)?;
+        let chains: Vec<ChainId> = match chains {
+            Some(x) => x,
+            None => index.offsets.keys().copied().collect(),
         };
-        // if chains.len() == 0 && !all {return Ok(ChainMap{ map: chainmap })}
-        let index_file: String = format!("{}.ix", &file);
-        // let index: FxHashMap<u64, (usize, usize)> = BinaryIndex::read_index(index_file)?;
-        let index: FxHashMap<u64, (u64, u64)> = Self::read_index(index_file, &chains, all)?;
-
-        let mut f = File::open(file)?;
-        for chain_id in index.keys() {
-            // get chain coordinates, panic if the chain Id is missing from the index file
-            let (start, end) = match index.get(&chain_id) {
-                Some(x) => {(x.0 as u64, x.1 as u64)},
+        Self::extract_offsets(file, &index, &chains, options)
+    }
+
+    // Private function for offset-based extraction
+    //
+    // # Arguments
+    // 
+    // * `file` - a chain file
+    // * `index` - an up-to-date index of the chain file
+    // * `chains` - IDs of chains to extract
+    // * `options` - parse options for malformed chains
+    fn extract_offsets<U>(file: U, index: &ChainIndex, chains: &[ChainId], options: &ParseOptions) -> Result<(ChainMap, ParseReport)>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let mut outcomes: Vec<Outcome> = Vec::with_capacity(chains.len());
+        if let Some(missing) = chains.iter().find(|x| !index.offsets.contains_key(x)) {
+            return Err(ChaintoolsError::MissingChain {
+                chain_id: *missing,
+                path: Some(file.as_ref().to_path_buf()),
+            }.into());
+        }
+
+        let path = file.as_ref();
+        let mut f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let len = f.metadata().map_err(|e| ChaintoolsError::io(path, e))?.len();
+        let mut bgzf = if index.bgzf {
+            Some(BgzfReader::new(BufReader::new(File::open(path).map_err(|e| ChaintoolsError::io(path, e))?)))
+        } else {
+            None
+        };
+        let mut chain_string: Vec<u8> = Vec::new();
+        let mut requested: FxHashSet<ChainId> = FxHashSet::default();
+        for chain_id in chains.iter().filter(|x| requested.insert(**x)) {
+            let (start, end) = index.offsets[chain_id];
+            // extract the chain bytes
+            chain_string.clear();
+            match bgzf.as_mut() {
+                Some(reader) => {
+                    // virtual offsets cannot be subtracted; read whole lines up to the end offset
+                    reader.seek_virtual(start)?;
+                    while reader.virtual_offset() < end {
+                        if reader.read_until(b'\n', &mut chain_string)? == 0 {
+                            break
+                        }
+                    }
+                }
                 None => {
-                    panic!("Chain ID {} was not found in the index file", chain_id)
+                    let size = end.checked_sub(start).filter(|_| end <= len).ok_or_else(|| ChaintoolsError::IndexMismatch {
+                        path: path.to_path_buf(),
+                        message: format!(
+                            "Chain {} spans bytes {}..{} of a {}-byte file; rebuild the index with BinaryIndex::index",
+                            chain_id, start, end, len
+                        ),
+                    })?;
+                    f.seek(SeekFrom::Start(start))?;
+                    chain_string.resize(size as usize, 0);
+                    f.read_exact(&mut chain_string[..])?;
                 }
+            }
+            // split chain into body and header; positions are only known
+            // for uncompressed files, where the record starts at a plain byte offset
+            let base = if index.bgzf { None } else { Some(start) };
+            let records = Records::detached(&chain_string, base);
+            let Some(record) = records.clone().next() else {
+                return Err(ChaintoolsError::MalformedHeader {
+                    line: None,
+                    offset: base,
+                    chain_id: Some(*chain_id),
+                    message: String::from("Empty chain record; the index might be corrupted"),
+                }.into());
             };
-            // extract the chain bytes
-            f.seek(SeekFrom::Start(start))?;
-            let mut chain_string = vec![0; (end-start) as usize];
-            f.read(&mut chain_string[..])?;
-            // split chain into body and header
-            let header_pos = memchr(b'\n', &chain_string).with_context(|| {
-                format!(
-                    "Failed to find newline separator in: {:?}",
-                    String::from_utf8_lossy(&chain_string)
-                )
-            })?;
-            let header: &[u8] = &chain_string[..header_pos];
-            let block: &[u8] = &chain_string[header_pos+1..];
-            let (id, chain) = Chain::from(header, block)?;
-            chainmap.insert(id, chain);
+            outcomes.push(Self::build_record(&records, &record, options)?);
+        }
+        Self::assemble(outcomes, options.duplicates, index.offsets.keys().max().copied())
+    }
+
+    /// Extract all chains overlapping a reference region from an indexed file
+    ///
+    /// Requires an up-to-date index (`<file>.ix`) created by
+    /// [`crate::io::indexer::BinaryIndex::index`], which is loaded and checked on every
+    /// call; see [`Reader::fetch_region_with`] for repeated lookups.
+    ///
+    /// # Arguments
+    /// * `file` - A path to the chain file
+    /// * `chrom` - A reference chromosome name
+    /// * `start` - A 0-based region start
+    /// * `end` - A 0-based region end (exclusive)
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of overlapping chains
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let chains = Reader::fetch_region("/path/to/chainfile", "chr15", 40_500_000, 40_600_000).unwrap();
+    /// ```
+    pub fn fetch_region<U>(file: U, chrom: &str, start: u64, end: u64) -> Result<ChainMap>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let index: ChainIndex = BinaryIndex::load(&file)?;
+        Self::fetch_region_with(file, &index, chrom, start, end)
+    }
+
+    /// Extract all chains overlapping a query region from an indexed file
+    ///
+    /// Query coordinates are expected on the positive strand; spans of chains
+    /// aligned to the negative query strand are converted accordingly.
+    ///
+    /// # Arguments
+    /// * `file` - A path to the chain file
+    /// * `chrom` - A query chromosome (scaffold) name
+    /// * `start` - A 0-based region start
+    /// * `end` - A 0-based region end (exclusive)
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of overlapping chains
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let chains = Reader::fetch_query_region("/path/to/chainfile", "scaffold_12", 0, 250_000).unwrap();
+    /// ```
+    pub fn fetch_query_region<U>(file: U, chrom: &str, start: u64, end: u64) -> Result<ChainMap>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let index: ChainIndex = BinaryIndex::load(&file)?;
+        Self::fetch_query_region_with(file, &index, chrom, start, end)
+    }
+
+    /// Extract all chains overlapping a reference region with an index loaded beforehand
+    ///
+    /// [`Reader::fetch_region`] loads and checks the index on every call; loading it once
+    /// with [`crate::io::indexer::BinaryIndex::load`] and passing it here saves that work
+    /// when many regions are looked up, e.g. one per transcript.
+    ///
+    /// # Arguments
+    /// * `file` - A path to the chain file
+    /// * `index` - The index of the chain file
+    /// * `chrom` - A reference chromosome name
+    /// * `start` - A 0-based region start
+    /// * `end` - A 0-based region end (exclusive)
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of overlapping chains
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::io::indexer::BinaryIndex;
+    /// use chaintools::io::reader::Reader;
+    ///
+    /// let path = std::env::temp_dir().join(format!("chaintools-doc-fetch-{}.chain", std::process::id()));
+    /// std::fs::write(&path, "chain 10 chr1 1000 + 0 100 chrA 1000 + 0 100 1\n100\n\n\
+    /// chain 20 chr1 1000 + 500 600 chrB 1000 - 0 100 2\n100\n\n").unwrap();
+    /// let file = path.display().to_string();
+    /// BinaryIndex::index(&file).unwrap();
+    ///
+    /// let index = BinaryIndex::load(&file).unwrap();
+    /// for (start, end, id) in [(10, 20, 1), (550, 560, 2)] {
+    ///     let chains = Reader::fetch_region_with(&file, &index, "chr1", start, end).unwrap();
+    ///     assert_eq!(chains.keys().copied().collect::<Vec<_>>(), vec![id]);
+    /// }
+    /// let chains = Reader::fetch_query_region_with(&file, &index, "chrB", 950, 1000).unwrap();
+    /// assert!(chains.get(&2).is_some());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(format!("{}.ix", file)).unwrap();
+    /// ```
+    pub fn fetch_region_with<U>(file: U, index: &ChainIndex, chrom: &str, start: u64, end: u64) -> Result<ChainMap>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        Self::fetch_side(file, index, BlockSide::Ref, chrom, start, end)
+    }
+
+    /// Extract all chains overlapping a query region with an index loaded beforehand
+    ///
+    /// See [`Reader::fetch_region_with`] and [`Reader::fetch_query_region`].
+    ///
+    /// # Arguments
+    /// * `file` - A path to the chain file
+    /// * `index` - The index of the chain file
+    /// * `chrom` - A query chromosome (scaffold) name
+    /// * `start` - A 0-based region start on the positive strand
+    /// * `end` - A 0-based region end (exclusive)
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainMap` of overlapping chains
+    pub fn fetch_query_region_with<U>(file: U, index: &ChainIndex, chrom: &str, start: u64, end: u64) -> Result<ChainMap>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        Self::fetch_side(file, index, BlockSide::Query, chrom, start, end)
+    }
+
+    // Private function for region-based extraction
+    fn fetch_side<U>(file: U, index: &ChainIndex, side: BlockSide, chrom: &str, start: u64, end: u64) -> Result<ChainMap>
+    where
+        U: AsRef<Path> + Debug + Display
+    {
+        let chains: Vec<ChainId> = index.regions.fetch(side, chrom, start, end);
+        Self::extract_offsets(file, index, &chains, &ParseOptions::default()).map(|(chains, _)| chains)
+    }
+}
+
+/// A streaming reader yielding chains one at a time.
+///
+/// Unlike [`Reader::from_file`], the input is never loaded as a whole:
+/// only the chain currently being parsed is kept in memory. `#` comment lines
+/// are collected as they are passed, see [`ChainReader::metadata`].
+pub struct ChainReader<R: BufRead> {
+    inner: R,
+    line: Vec<u8>,
+    pending: Vec<u8>,
+    header: Vec<u8>,
+    block: Vec<u8>,
+    // number of lines and bytes read so far
+    lines: u64,
+    bytes: u64,
+    // line number and byte offset of the last line read, the pending header and the current header
+    line_at: (u64, u64),
+    pending_at: (u64, u64),
+    header_at: (u64, u64),
+    // byte offset of the current block
+    block_offset: u64,
+    metadata: Metadata,
+}
+
+impl<'a> ChainReader<Box<dyn BufRead + 'a>> {
+    /// Create a new streaming reader from a plain or compressed chain file.
+    ///
+    /// # Arguments
+    /// * `file` - A path to a chain file.
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainReader`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use chaintools::io::reader::ChainReader;
+    ///
+    /// for chain in ChainReader::from_file("/path/to/chainfile.chain.gz")? {
+    ///     let chain = chain?;
+    ///     println!("{}", chain.header());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_file<T>(file: T) -> Result<Self>
+    where
+        T: AsRef<Path> + Debug,
+    {
+        let path = file.as_ref();
+        let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+        let mut f = BufReader::new(f);
+        f.fill_buf().map_err(|e| ChaintoolsError::io(path, e))?;
+        Ok(Self::new(Compression::decompress(f)?))
+    }
+
+    /// Create a new streaming reader from any plain or compressed byte source.
+    ///
+    /// The compression format is detected from the magic bytes of the source.
+    ///
+    /// # Arguments
+    /// * `reader` - A source of chain data, e.g. stdin.
+    ///
+    /// # Returns
+    /// A `Result` containing a `ChainReader`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # fn main() -> anyhow::Result<()> {
+    /// use chaintools::io::reader::ChainReader;
+    ///
+    /// for chain in ChainReader::from_reader(std::io::stdin().lock())? {
+    ///     println!("{}", chain?.header());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_reader<R: Read + 'a>(reader: R) -> Result<Self> {
+        Ok(Self::new(Compression::decompress(BufReader::new(reader))?))
+    }
+}
+
+impl<R: BufRead> ChainReader<R> {
+    /// Create a new streaming reader over any buffered source.
+    ///
+    /// # Arguments
+    /// * `inner` - A `BufRead` source containing chain records.
+    ///
+    /// # Returns
+    /// A `ChainReader`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use chaintools::io::reader::ChainReader;
+    ///
+    /// let data = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n10\n\n";
+    /// let chains = ChainReader::new(&data[..]).collect::<Result<Vec<_>, _>>().unwrap();
+    ///
+    /// assert_eq!(chains.len(), 1);
+    /// ```
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+            pending: Vec::new(),
+            header: Vec::new(),
+            block: Vec::new(),
+            lines: 0,
+            bytes: 0,
+            line_at: (0, 0),
+            pending_at: (0, 0),
+            header_at: (0, 0),
+            block_offset: 0,
+            metadata: Metadata::new(),
         }
-        Ok(ChainMap{ map: chainmap } )
+    }
+
+    /// Metadata from the `#` comment lines read so far.
+    ///
+    /// # Returns
+    /// * &Metadata
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::ChainReader;
+    ///
+    /// let data = b"#source: hg38.mm39\nchain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n";
+    /// let mut reader = ChainReader::new(&data[..]);
+    /// reader.next().unwrap().unwrap();
+    ///
+    /// assert_eq!(reader.metadata().get("source"), Some("hg38.mm39"));
+    /// ```
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Read the next line into the line buffer; returns false on EOF
+    fn next_line(&mut self) -> Result<bool> {
+        self.line.clear();
+        let n = self
+            .inner
+            .read_until(b'\n', &mut self.line)
+            .map_err(ChaintoolsError::from)?;
+        self.lines += 1;
+        self.line_at = (self.lines, self.bytes);
+        self.bytes += n as u64;
+        Ok(n > 0)
+    }
+
+    /// Collect the next header and alignment block into the inner buffers;
+    /// returns false if the source has been exhausted
+    fn next_record(&mut self) -> Result<bool> {
+        self.header.clear();
+        self.block.clear();
+
+        // the header might have been already consumed while reading the previous block
+        if !self.pending.is_empty() {
+            std::mem::swap(&mut self.header, &mut self.pending);
+            self.header_at = self.pending_at;
+        } else {
+            loop {
+                if !self.next_line()? {
+                    return Ok(false);
+                }
+                if self.line.starts_with(b"chain") {
+                    self.header.extend_from_slice(&self.line);
+                    self.header_at = self.line_at;
+                    break;
+                }
+                if self.line.starts_with(b"#") {
+                    self.metadata.push(&String::from_utf8_lossy(self.line[1..].trim_ascii_end()));
+                    continue;
+                }
+                if !self.line.trim_ascii().is_empty() {
+                    return Err(ChaintoolsError::MalformedHeader {
+                        line: Some(self.line_at.0),
+                        offset: Some(self.line_at.1),
+                        chain_id: None,
+                        message: format!(
+                            "Unexpected line outside of a chain record: {:?}",
+                            String::from_utf8_lossy(&self.line)
+                        ),
+                    }.into());
+                }
+            }
+        }
+        self.block_offset = self.header_at.1 + self.header.len() as u64;
+        let len = self.header.trim_ascii_end().len();
+        self.header.truncate(len);
+
+        // the block ends with an empty line, a comment, the next header or EOF
+        while self.next_line()? {
+            if self.line.starts_with(b"#") {
+                self.metadata.push(&String::from_utf8_lossy(self.line[1..].trim_ascii_end()));
+                break;
+            }
+            if self.line.starts_with(b"chain") {
+                self.pending.extend_from_slice(&self.line);
+                self.pending_at = self.line_at;
+                break;
+            }
+            if self.line.trim_ascii().is_empty() {
+                break;
+            }
+            self.block.extend_from_slice(&self.line);
+            if !self.block.ends_with(b"\n") {
+                self.block.push(b'\n');
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Iterator for ChainReader<R> {
+    type Item = Result<Chain>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_record() {
+            Ok(false) => None,
+            Ok(true) => Some(
+                Chain::from(&self.header, &self.block)
+                    .map(|(_, chain)| chain)
+                    .map_err(|e| match e.downcast::<ChaintoolsError>() {
+                        Ok(e @ ChaintoolsError::MalformedHeader { .. }) => {
+                            e.shifted(Some(self.header_at.0 - 1), Some(self.header_at.1)).into()
+                        }
+                        Ok(e @ ChaintoolsError::BadBlock { .. }) => {
+                            e.shifted(Some(self.header_at.0), Some(self.block_offset)).into()
+                        }
+                        Ok(e) => e.into(),
+                        Err(e) => e,
+                    }),
+            ),
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Outcome of parsing a single chain record
+enum Outcome {
+    // the line number and byte offset of the header, when known
+    Parsed(ChainId, Chain, (Option<u64>, Option<u64>)),
+    Repaired(Option<ChainId>, Chain, ParseIssue),
+    Skipped(ParseIssue),
+}
+
+/// A chain record of data held in memory
+#[derive(Clone, Copy)]
+pub(crate) struct Record<'a> {
+    pub(crate) header: &'a [u8],
+    pub(crate) block: &'a [u8],
+    /// The 1-based line number of the header, when known
+    pub(crate) line: Option<u64>,
+}
+
+/// Iterator over the chain records of chain data held in memory.
+///
+/// Blank and `#` comment lines between chains are skipped; a block extends up to
+/// the next line starting with `chain` or `#`, or to the end of data. Line numbers
+/// are counted as the data is walked, so every byte is scanned once.
+#[derive(Clone)]
+pub(crate) struct Records<'a> {
+    data: &'a [u8],
+    pos: usize,
+    base: Option<u64>,
+    // the 1-based line number at `pos`, when known
+    line: Option<u64>,
+}
+
+impl<'a> Records<'a> {
+    /// Iterate over a whole chain file
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, base: Some(0), line: Some(1) }
+    }
+
+    /// Iterate over a piece of a chain file starting at a known byte offset, if any;
+    /// line numbers cannot be known for such pieces
+    pub(crate) fn detached(data: &'a [u8], base: Option<u64>) -> Self {
+        Self { data, pos: 0, base, line: None }
+    }
+
+    /// Shift the position of a parsing error raised on a record
+    /// yielded by this iterator to a position in the data
+    pub(crate) fn locate(&self, err: anyhow::Error, record: &Record<'_>) -> anyhow::Error {
+        let err = match err.downcast::<ChaintoolsError>() {
+            Ok(err) => err,
+            Err(err) => return err,
+        };
+        let (line, offset) = match err {
+            ChaintoolsError::MalformedHeader { .. } => self.position(record),
+            ChaintoolsError::BadBlock { .. } => self.block_position(record),
+            _ => return err.into(),
+        };
+        err.shifted(line.map(|x| x - 1), offset).into()
+    }
+
+    /// Get the 1-based line number and the byte offset of the header of a record, when known
+    pub(crate) fn position(&self, record: &Record<'_>) -> (Option<u64>, Option<u64>) {
+        (record.line, self.offset(record.header))
+    }
+
+    /// Get the 1-based line number and the byte offset of the alignment block of a record, when known
+    pub(crate) fn block_position(&self, record: &Record<'_>) -> (Option<u64>, Option<u64>) {
+        // the block starts on the line following its header
+        (record.line.map(|x| x + 1), self.offset(record.block))
+    }
+
+    /// Get the byte offset of a slice of the data, when known
+    pub(crate) fn offset(&self, slice: &[u8]) -> Option<u64> {
+        let offset = (slice.as_ptr() as usize).saturating_sub(self.data.as_ptr() as usize);
+        self.base.map(|x| x + offset as u64)
+    }
+
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip blank and comment lines between chains
+        while self.pos < self.data.len() {
+            let rest = &self.data[self.pos..];
+            let line_end = memchr(b'\n', rest).map_or(rest.len(), |x| x + 1);
+            if !rest.starts_with(b"#") && !rest[..line_end].iter().all(|x| x.is_ascii_whitespace()) {
+                break;
+            }
+            self.pos += line_end;
+            self.line = self.line.map(|x| x + 1);
+        }
+        if self.pos >= self.data.len() {
+            return None;
+        }
+
+        let rest = &self.data[self.pos..];
+        let header_end = memchr(b'\n', rest).unwrap_or(rest.len());
+        let header = &rest[..header_end];
+        let block_start = (header_end + 1).min(rest.len());
+        let body = &rest[block_start..];
+        // the block ends at the next header or comment line
+        let block_end = if body.starts_with(b"chain") || body.starts_with(b"#") {
+            0
+        } else {
+            let next_chain = memmem::find(body, b"\nchain").map_or(body.len(), |x| x + 1);
+            memmem::find(&body[..next_chain], b"\n#").map_or(next_chain, |x| x + 1)
+        };
+        let line = self.line;
+        self.line = line.map(|x| x + memchr_iter(b'\n', &rest[..block_start + block_end]).count() as u64);
+        self.pos += block_start + block_end;
+        Some(Record { header, block: &body[..block_end], line })
     }
 }
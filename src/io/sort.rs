@@ -0,0 +1,471 @@
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    fmt::Debug,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::cmap::align::AlignmentRecord;
+use crate::cmap::chain::{Chain, ChainId};
+use crate::cmap::map::{ChainOrder, Metadata};
+use crate::error::ChaintoolsError;
+use crate::io::codec::{decode_chain, encode_chain};
+use crate::io::compress::Compression;
+use crate::io::reader::ChainReader;
+use crate::io::writer::Writer;
+
+/// Default memory budget for the chains of a sorted run (512 MiB)
+pub const DEFAULT_RUN_BYTES: usize = 512 << 20;
+/// Maximum number of runs merged at once; more runs are merged in several passes
+const MAX_FAN_IN: usize = 64;
+
+/// Options controlling an external merge-sort
+///
+/// # Example
+/// ```
+/// use chaintools::cmap::map::ChainOrder;
+/// use chaintools::io::compress::Compression;
+/// use chaintools::io::sort::SortOptions;
+///
+/// let options = SortOptions::new(ChainOrder::Reference)
+///     .with_run_bytes(64 << 20)
+///     .with_renumbering(true)
+///     .with_compression(Compression::Bgzf);
+/// assert!(options.renumber);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SortOptions {
+    /// The order of the output chains
+    pub order: ChainOrder,
+    /// Approximate memory taken by the chains of a run before it is spilled to disk
+    pub run_bytes: usize,
+    /// Directory for temporary runs; the system temporary directory if `None`
+    pub temp_dir: Option<PathBuf>,
+    /// Give output chains consecutive IDs starting at 1, in output order
+    pub renumber: bool,
+    /// The compression format of the output
+    pub compression: Compression,
+}
+
+impl Default for SortOptions {
+    fn default() -> Self {
+        Self::new(ChainOrder::Score)
+    }
+}
+
+impl SortOptions {
+    /// Create sort options for a given order, with the default memory budget,
+    /// no renumbering and uncompressed output
+    ///
+    /// # Arguments
+    /// * `order` - The order of the output chains
+    ///
+    /// # Returns
+    /// * SortOptions
+    pub fn new(order: ChainOrder) -> Self {
+        Self {
+            order,
+            run_bytes: DEFAULT_RUN_BYTES,
+            temp_dir: None,
+            renumber: false,
+            compression: Compression::None,
+        }
+    }
+
+    /// Set the memory budget of a sorted run
+    ///
+    /// # Arguments
+    /// * `bytes` - Approximate memory taken by the chains of a run
+    ///
+    /// # Returns
+    /// * SortOptions
+    pub fn with_run_bytes(mut self, bytes: usize) -> Self {
+        self.run_bytes = bytes;
+        self
+    }
+
+    /// Set the directory for temporary runs
+    ///
+    /// # Arguments
+    /// * `dir` - An existing directory
+    ///
+    /// # Returns
+    /// * SortOptions
+    pub fn with_temp_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    /// Set whether output chains get consecutive IDs
+    ///
+    /// # Arguments
+    /// * `renumber` - Renumber chains from 1 in output order
+    ///
+    /// # Returns
+    /// * SortOptions
+    pub fn with_renumbering(mut self, renumber: bool) -> Self {
+        self.renumber = renumber;
+        self
+    }
+
+    /// Set the compression format of the output
+    ///
+    /// # Arguments
+    /// * `compression` - A compression format, e.g. [`Compression::Bgzf`] for an indexable output
+    ///
+    /// # Returns
+    /// * SortOptions
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+}
+
+/// Figures of a finished merge-sort
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortSummary {
+    /// Number of chains written
+    pub chains: u64,
+    /// Number of sorted runs spilled to disk; 0 if all chains fitted in memory
+    pub runs: usize,
+}
+
+/// External merge-sort of chain files, in the spirit of UCSC `chainMergeSort`
+///
+/// Chains are streamed from every input with [`ChainReader`], so no input is ever
+/// loaded as a whole. Chains are collected into runs up to [`SortOptions::run_bytes`],
+/// every run is sorted and spilled to a temporary file in the compact binary encoding
+/// of [`crate::io::codec`], and all runs are finally k-way merged into a single
+/// plain-text output. Temporary files are removed once the sort ends, even on errors.
+///
+/// Chains keep their IDs unless [`SortOptions::renumber`] is set; IDs repeated across
+/// inputs are written as they are.
+pub struct MergeSort;
+
+impl MergeSort {
+    /// Merge and sort chain files into a chain file
+    ///
+    /// # Arguments
+    /// * `inputs` - Paths to plain or compressed chain files
+    /// * `output` - A path to the output file
+    /// * `options` - Sort options
+    ///
+    /// # Returns
+    /// * Result<SortSummary>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::sort::{MergeSort, SortOptions};
+    ///
+    /// let dir = std::env::temp_dir().join(format!("chaintools-doc-sort-{}", std::process::id()));
+    /// let spill = dir.join("spill");
+    /// std::fs::create_dir_all(&spill).unwrap();
+    ///
+    /// // 70 chains over two inputs, their scores interleaving across the inputs
+    /// let chain = |score: u64, id: u64| format!("chain {} chr1 1000 + {} {} chr2 1000 + 0 10 {}\n10\n\n", score, id, id + 10, id);
+    /// let inputs = [dir.join("job1.chain"), dir.join("job2.chain")];
+    /// std::fs::write(&inputs[0], (1..=40).map(|i| chain(2 * i, i)).collect::<String>()).unwrap();
+    /// std::fs::write(&inputs[1], (41..=70).map(|i| chain(2 * (i - 40) - 1, i)).collect::<String>()).unwrap();
+    ///
+    /// // a 1-byte budget spills every chain to a run of its own, more runs than are merged at once
+    /// let options = SortOptions::new(ChainOrder::Score)
+    ///     .with_run_bytes(1)
+    ///     .with_temp_dir(&spill)
+    ///     .with_renumbering(true);
+    /// let output = dir.join("all.sorted.chain");
+    /// let summary = MergeSort::to_file(&inputs, &output, &options).unwrap();
+    /// assert_eq!((summary.chains, summary.runs), (70, 70));
+    ///
+    /// // descending scores, IDs renumbered in output order
+    /// let sorted = std::fs::read_to_string(&output).unwrap();
+    /// let headers: Vec<Vec<u64>> = sorted
+    ///     .lines()
+    ///     .filter(|x| x.starts_with("chain"))
+    ///     .map(|x| x.split(' ').filter_map(|field| field.parse().ok()).collect())
+    ///     .collect();
+    /// assert_eq!(headers.len(), 70);
+    /// assert_eq!((headers[0][0], headers[69][0]), (80, 1));
+    /// assert!(headers.windows(2).all(|x| x[0][0] > x[1][0]));
+    /// assert!(headers.iter().enumerate().all(|(i, x)| x[x.len() - 1] == i as u64 + 1));
+    ///
+    /// // temporary runs are removed
+    /// assert_eq!(std::fs::read_dir(&spill).unwrap().count(), 0);
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn to_file<P, T>(inputs: &[P], output: T, options: &SortOptions) -> Result<SortSummary>
+    where
+        P: AsRef<Path> + Debug,
+        T: AsRef<Path> + Debug,
+    {
+        let file = File::create(&output).with_context(|| format!("Failed to create file {:?}", output))?;
+        Self::to_writer(inputs, BufWriter::new(file), options)
+    }
+
+    /// Merge and sort chain files into any writer
+    ///
+    /// Input metadata (`#` comment lines) is written first, each distinct line once.
+    ///
+    /// # Arguments
+    /// * `inputs` - Paths to plain or compressed chain files
+    /// * `out` - A writer, e.g. stdout or a file
+    /// * `options` - Sort options
+    ///
+    /// # Returns
+    /// * Result<SortSummary>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::sort::{MergeSort, SortOptions};
+    ///
+    /// let dir = std::env::temp_dir().join(format!("chaintools-doc-sort-writer-{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    /// let inputs = [dir.join("job1.chain"), dir.join("job2.chain")];
+    /// std::fs::write(&inputs[0], "#netChains v1\nchain 10 chr2 100 + 0 10 chrA 100 + 0 10 1\n10\n\n").unwrap();
+    /// std::fs::write(&inputs[1], "#netChains v1\nchain 20 chr1 100 + 50 60 chrA 100 + 50 60 2\n10\n\n").unwrap();
+    ///
+    /// let mut out = Vec::new();
+    /// let summary = MergeSort::to_writer(&inputs, &mut out, &SortOptions::new(ChainOrder::Reference)).unwrap();
+    /// assert_eq!((summary.chains, summary.runs), (2, 0));
+    /// let out = String::from_utf8(out).unwrap();
+    /// assert!(out.starts_with("#netChains v1\nchain 20 chr1 100 + 50 60 chrA 100 + 50 60 2\n"));
+    /// assert_eq!(out.matches("#netChains").count(), 1);
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    pub fn to_writer<P, W>(inputs: &[P], out: W, options: &SortOptions) -> Result<SortSummary>
+    where
+        P: AsRef<Path> + Debug,
+        W: Write,
+    {
+        let spill = SpillDir::create(options.temp_dir.as_deref())?;
+        let mut metadata = Metadata::new();
+        let mut runs: Vec<PathBuf> = Vec::new();
+        let mut buffer: Vec<Chain> = Vec::new();
+        let mut buffered: usize = 0;
+
+        for input in inputs {
+            let mut reader = ChainReader::from_file(input)?;
+            for chain in reader.by_ref() {
+                let chain = chain.with_context(|| format!("Failed to read chain file {:?}", input))?;
+                buffered += Self::footprint(&chain);
+                buffer.push(chain);
+                if buffered >= options.run_bytes {
+                    runs.push(Self::spill(&mut buffer, &spill, runs.len(), options.order)?);
+                    buffered = 0;
+                }
+            }
+            for line in reader.metadata().lines() {
+                if !metadata.lines().any(|x| x == line) {
+                    metadata.push(line);
+                }
+            }
+        }
+
+        let mut summary = SortSummary { chains: 0, runs: 0 };
+        if runs.is_empty() {
+            // a stable sort keeps chains comparing equal in input order
+            buffer.par_sort_by(|a, b| options.order.compare(a, b));
+            Self::write(buffer.into_iter().map(Ok), out, &metadata, options, &mut summary)?;
+            return Ok(summary);
+        }
+        if !buffer.is_empty() {
+            runs.push(Self::spill(&mut buffer, &spill, runs.len(), options.order)?);
+        }
+        summary.runs = runs.len();
+
+        // merge in several passes if there are too many runs to keep open at once
+        let mut pass: usize = 0;
+        while runs.len() > MAX_FAN_IN {
+            pass += 1;
+            let mut merged: Vec<PathBuf> = Vec::new();
+            for (i, group) in runs.chunks(MAX_FAN_IN).enumerate() {
+                let path = spill.path(&format!("pass{}-{}", pass, i));
+                let mut run = BufWriter::new(File::create(&path).map_err(|e| ChaintoolsError::io(&path, e))?);
+                for chain in RunMerge::open(group, options.order)? {
+                    Self::write_record(&chain?, &mut run, &path)?;
+                }
+                run.flush().map_err(|e| ChaintoolsError::io(&path, e))?;
+                for path in group {
+                    let _ = fs::remove_file(path);
+                }
+                merged.push(path);
+            }
+            runs = merged;
+        }
+
+        Self::write(RunMerge::open(&runs, options.order)?, out, &metadata, options, &mut summary)?;
+        Ok(summary)
+    }
+
+    // Private writer of the final output
+    fn write<I, W>(chains: I, out: W, metadata: &Metadata, options: &SortOptions, summary: &mut SortSummary) -> Result<()>
+    where
+        I: Iterator<Item = Result<Chain>>,
+        W: Write,
+    {
+        options
+            .compression
+            .compress(out, |w| {
+                Writer::write_metadata(metadata, &mut *w)?;
+                for chain in chains {
+                    let mut chain = chain?;
+                    summary.chains += 1;
+                    if options.renumber {
+                        chain.id = summary.chains as ChainId;
+                    }
+                    w.write_all(chain.to_string().as_bytes())?;
+                }
+                Ok(())
+            })?
+            .flush()?;
+        Ok(())
+    }
+
+    // Private sorter and writer of a run to a temporary file
+    fn spill(buffer: &mut Vec<Chain>, spill: &SpillDir, n: usize, order: ChainOrder) -> Result<PathBuf> {
+        // stable within the run, as RunMerge is across runs
+        buffer.par_sort_by(|a, b| order.compare(a, b));
+        let path = spill.path(&format!("run{}", n));
+        let mut run = BufWriter::new(File::create(&path).map_err(|e| ChaintoolsError::io(&path, e))?);
+        for chain in buffer.drain(..) {
+            Self::write_record(&chain, &mut run, &path)?;
+        }
+        run.flush().map_err(|e| ChaintoolsError::io(&path, e))?;
+        Ok(path)
+    }
+
+    // Private writer of a length-prefixed encoded chain
+    fn write_record<W: Write>(chain: &Chain, out: &mut W, path: &Path) -> Result<()> {
+        chain.records()?;
+        let mut record: Vec<u8> = Vec::new();
+        encode_chain(chain, &mut record);
+        out.write_all(&(record.len() as u64).to_le_bytes())
+            .and_then(|_| out.write_all(&record))
+            .map_err(|e| ChaintoolsError::io(path, e))?;
+        Ok(())
+    }
+
+    // Private estimate of the memory taken by a chain
+    fn footprint(chain: &Chain) -> usize {
+        size_of::<Chain>()
+            + chain.refs.chr.len()
+            + chain.query.chr.len()
+            + chain.alignment.len() * size_of::<AlignmentRecord>()
+    }
+}
+
+/// A k-way merge of sorted runs, yielding chains in order
+///
+/// Ties are broken by run index, so chains comparing equal keep their input order.
+struct RunMerge {
+    runs: Vec<(BufReader<File>, PathBuf)>,
+    heap: BinaryHeap<Head>,
+    order: ChainOrder,
+}
+
+/// The next chain of a run, ordered for a min-heap
+struct Head {
+    chain: Chain,
+    run: usize,
+    order: ChainOrder,
+}
+
+impl Ord for Head {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: the smallest chain must compare as the greatest
+        self.order
+            .compare(&other.chain, &self.chain)
+            .then(other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+impl RunMerge {
+    // Open all runs and read their first chains
+    fn open(paths: &[PathBuf], order: ChainOrder) -> Result<Self> {
+        let mut merge = Self { runs: Vec::with_capacity(paths.len()), heap: BinaryHeap::new(), order };
+        for (i, path) in paths.iter().enumerate() {
+            let f = File::open(path).map_err(|e| ChaintoolsError::io(path, e))?;
+            merge.runs.push((BufReader::new(f), path.clone()));
+            merge.advance(i)?;
+        }
+        Ok(merge)
+    }
+
+    // Push the next chain of a run onto the heap, if any
+    fn advance(&mut self, run: usize) -> Result<()> {
+        let (reader, path) = &mut self.runs[run];
+        let mut len = [0u8; 8];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(ChaintoolsError::io(path.as_path(), e).into()),
+        }
+        let mut record = vec![0u8; u64::from_le_bytes(len) as usize];
+        reader.read_exact(&mut record).map_err(|e| ChaintoolsError::io(path.as_path(), e))?;
+        let chain = decode_chain(&record).with_context(|| format!("Corrupted sort run {:?}", path))?;
+        self.heap.push(Head { chain, run, order: self.order });
+        Ok(())
+    }
+}
+
+impl Iterator for RunMerge {
+    type Item = Result<Chain>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Head { chain, run, .. } = self.heap.pop()?;
+        Some(self.advance(run).map(|_| chain))
+    }
+}
+
+/// Number of spill directories created by this process, keeping their names unique
+static SPILL_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory holding sorted runs, removed when dropped
+struct SpillDir {
+    path: PathBuf,
+}
+
+impl SpillDir {
+    // Create a uniquely named directory under `parent` or the system temporary directory
+    fn create(parent: Option<&Path>) -> Result<Self> {
+        let parent = parent.map_or_else(std::env::temp_dir, Path::to_path_buf);
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |x| x.subsec_nanos());
+        let n = SPILL_DIRS.fetch_add(1, AtomicOrdering::Relaxed);
+        let path = parent.join(format!("chaintools-sort-{}-{}-{}", std::process::id(), nanos, n));
+        fs::create_dir(&path).map_err(|e| ChaintoolsError::io(&path, e))?;
+        Ok(Self { path })
+    }
+
+    // Path of a file in the directory
+    fn path(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for SpillDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -1,68 +1,458 @@
-use anyhow::Result;
-use bincode;
-use serde::Serialize;
-use std::{fmt::Debug, fs::File, io::prelude::*, path::Path};
+use anyhow::{Context, Result};
+use fxhash::FxHashSet;
+use std::{borrow::Borrow, fmt::Debug, fs::File, io::{prelude::*, BufWriter}, path::Path};
+
+use crate::cmap::chain::{Chain, ChainId};
+use crate::cmap::map::{ChainMap, ChainOrder, Metadata};
+use crate::error::ChaintoolsError;
+use crate::io::codec::{encode_chain, encode_table};
+use crate::io::compress::Compression;
+
+/// Magic bytes opening and closing every chaintools binary container
+pub const BIN_MAGIC: &[u8; 8] = b"CHAINBIN";
+/// Current version of the binary container layout
+pub const BIN_VERSION: u16 = 2;
+/// Size of the binary container header (magic bytes and version)
+pub(crate) const BIN_HEADER_SIZE: u64 = 10;
 
 /// Write chaintools object to a file
 pub struct Writer;
 
 impl Writer {
-    /// Encode and write chaintools object to a file
+    /// Encode and write chains to a binary container file
+    ///
+    /// Every chain is serialized independently and a footer table maps chain IDs
+    /// to their records, so [`crate::io::reader::Reader::load_chain`] and
+    /// [`crate::io::reader::Reader::load_chains`] only decode the requested chains
     ///
     /// # Arguments
-    /// * `data` - A chaintools object
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
+    /// * `path` - A path to the output file
     ///
     /// # Returns
     /// * Result<()>
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let data = Reader::from_bytes(line).unwrap();
+    /// Writer::to_bin(data.values(), "/path/to/output.bin").unwrap();
     /// ```
-    /// use chaintools as chain;
+    pub fn to_bin<I, B, T>(chains: I, path: T) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        T: AsRef<Path> + Debug,
+    {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        let mut out = BufWriter::new(file);
+        Self::write_bin(chains, &mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Encode, compress and write chains to a binary container file
+    ///
+    /// Compressed containers cannot be seeked into and are decompressed
+    /// as a whole when read, but still only requested chains are decoded
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
+    /// * `path` - A path to the output file
     ///
+    /// # Returns
+    /// * Result<()>
     ///
-    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n\n";
-    /// let data = chain::Reader::from_bytes(line)?;
-    /// chain::Writer::write_index("/path/to/output.bin", data).unwrap();
+    /// # Example
+    /// ```no_run
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let data = Reader::from_bytes(line).unwrap();
+    /// Writer::to_bin_gz(data.values(), "/path/to/output.bin.gz").unwrap();
     /// ```
-    pub fn to_bin<K, T>(data: K, path: T) -> Result<()>
+    ///
+    pub fn to_bin_gz<I, B, T>(chains: I, path: T) -> Result<()>
     where
-        K: Serialize + Clone + Debug + Sync + Send,
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
         T: AsRef<Path> + Debug,
     {
-        let encoded: Vec<u8> = bincode::serialize(&data)?;
-        let mut file = File::create(path)?;
-        file.write_all(&encoded).expect("Failed to write to file");
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Compression::Gzip
+            .compress(BufWriter::new(file), |w| Self::write_bin(chains, w))?
+            .flush()?;
         Ok(())
     }
 
-    /// Encode, compress and write chaintools object to a file
+    /// Write chains as a binary container to any writer
+    ///
+    /// The container starts with [`BIN_MAGIC`] and [`BIN_VERSION`], followed by
+    /// one compactly encoded record per chain (see [`crate::io::codec`]) and a footer table
+    /// of chain IDs and record lengths in the order they were written.
+    /// The file ends with the footer offset and the magic bytes again.
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains
+    /// * `out` - A writer
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub fn write_bin<I, B, W>(chains: I, mut out: W) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        W: Write,
+    {
+        let mut seen: FxHashSet<ChainId> = FxHashSet::default();
+        let mut table: Vec<(ChainId, u64)> = Vec::new();
+        out.write_all(BIN_MAGIC)?;
+        out.write_all(&BIN_VERSION.to_le_bytes())?;
+        let mut offset: u64 = BIN_HEADER_SIZE;
+        let mut record: Vec<u8> = Vec::new();
+        for chain in chains {
+            let chain = chain.borrow();
+            if !seen.insert(chain.id) {
+                return Err(ChaintoolsError::DuplicateChain { chain_id: chain.id, line: None, offset: None }.into());
+            }
+            // malformed lazily parsed blocks fail here instead of being written without blocks
+            chain.records()?;
+            record.clear();
+            encode_chain(chain, &mut record);
+            table.push((chain.id, record.len() as u64));
+            out.write_all(&record)?;
+            offset += record.len() as u64;
+        }
+        record.clear();
+        encode_table(&table, &mut record);
+        out.write_all(&record)?;
+        out.write_all(&offset.to_le_bytes())?;
+        out.write_all(BIN_MAGIC)?;
+        Ok(())
+    }
+
+    /// Write chains as a plain-text .chain file
+    ///
+    /// Chains carry no file-level metadata; see [`Writer::map_to_chain`] to keep
+    /// the `#` comment lines of a ChainMap.
     ///
     /// # Arguments
-    /// * `data` - A chaintools object
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
     /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
     ///
     /// # Returns
     /// * Result<()>
     ///
     /// # Example
+    /// ```no_run
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let chains = Reader::from_file("/path/to/input.chain").unwrap();
+    /// Writer::to_chain(chains.values(), "/path/to/output.chain", ChainOrder::Score).unwrap();
     /// ```
-    /// use chaintools as chain;
+    pub fn to_chain<I, B, T>(chains: I, path: T, order: ChainOrder) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        T: AsRef<Path> + Debug,
+    {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::to_writer(chains, BufWriter::new(file), order, Compression::None)
+    }
+
+    /// Write chains as a gzip-compressed plain-text .chain file
+    ///
+    /// Chains carry no file-level metadata; see [`Writer::map_to_chain_gz`] to keep
+    /// the `#` comment lines of a ChainMap.
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
+    /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
     ///
-    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n\n";
-    /// let data = chain::Reader::from_bytes(line)?;
-    /// chain::Writer::write_index_gz("/path/to/output.bin.gz", data).unwrap();
+    /// # Example
+    /// ```no_run
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let chains = Reader::from_file("/path/to/input.chain").unwrap();
+    /// Writer::to_chain_gz(chains.values(), "/path/to/output.chain.gz", ChainOrder::Reference).unwrap();
     /// ```
+    pub fn to_chain_gz<I, B, T>(chains: I, path: T, order: ChainOrder) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        T: AsRef<Path> + Debug,
+    {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::to_writer(chains, BufWriter::new(file), order, Compression::Gzip)
+    }
+
+    /// Write chains as a BGZF-compressed plain-text .chain file
+    ///
+    /// BGZF files decompress with any gzip reader, but can also be indexed with
+    /// [`crate::io::indexer::BinaryIndex::index`] and then queried with
+    /// [`crate::io::reader::Reader::extract_ix`] or [`crate::io::reader::Reader::fetch_region`].
+    /// Chains carry no file-level metadata; see [`Writer::map_to_chain_bgzf`] to keep
+    /// the `#` comment lines of a ChainMap.
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
+    /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{indexer::BinaryIndex, reader::Reader, writer::Writer};
     ///
-    pub fn to_bin_gz<K, T>(data: K, path: T) -> Result<()>
+    /// let chains = Reader::from_file("/path/to/input.chain").unwrap();
+    /// Writer::to_chain_bgzf(chains.values(), "/path/to/output.chain.gz", ChainOrder::Reference).unwrap();
+    /// BinaryIndex::index("/path/to/output.chain.gz").unwrap();
+    /// let subset = Reader::extract_ix("/path/to/output.chain.gz", Some(vec![1, 2])).unwrap();
+    /// ```
+    pub fn to_chain_bgzf<I, B, T>(chains: I, path: T, order: ChainOrder) -> Result<()>
     where
-        K: Serialize + Clone + Debug + Sync + Send,
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
         T: AsRef<Path> + Debug,
     {
-        let encoded: Vec<u8> = bincode::serialize(&data)?;
-        let mut file =
-            flate2::write::GzEncoder::new(File::create(path)?, flate2::Compression::default());
-        file.write_all(&encoded).expect("Failed to write to file");
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::to_writer(chains, BufWriter::new(file), order, Compression::Bgzf)
+    }
+
+    /// Write chains as a plain-text .chain stream to any writer, optionally compressed
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains (e.g. `ChainMap::values()` or a `Vec<Chain>`)
+    /// * `out` - A writer, e.g. stdout, a file or a `Vec<u8>`
+    /// * `order` - The order in which chains are written
+    /// * `compression` - The compression format of the output
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::compress::Compression;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let chains = Reader::from_bytes(line).unwrap();
+    ///
+    /// let mut out: Vec<u8> = Vec::new();
+    /// Writer::to_writer(chains.values(), &mut out, ChainOrder::Id, Compression::Gzip).unwrap();
+    /// let back = Reader::from_reader(&out[..]).unwrap();
+    ///
+    /// assert_eq!(back.get(&1).unwrap().to_string(), chains.get(&1).unwrap().to_string());
+    /// ```
+    pub fn to_writer<I, B, W>(chains: I, out: W, order: ChainOrder, compression: Compression) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        W: Write,
+    {
+        compression
+            .compress(out, |w| Self::write_chains(chains, w, order))?
+            .flush()?;
+        Ok(())
+    }
+
+    /// Write chains in the plain-text .chain format to any writer
+    ///
+    /// Chains are streamed as they come for `ChainOrder::Unsorted`;
+    /// any other ordering collects the chains before writing them.
+    ///
+    /// # Arguments
+    /// * `chains` - Any iterable of chains
+    /// * `out` - A writer
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let line = b"chain 4900 chrY 58368225 + 25985403 25985638 chr5 151006098 - 43257292 43257528 1\n9\t1\t0\n225\n\n";
+    /// let chains = Reader::from_bytes(line).unwrap();
+    /// let mut out: Vec<u8> = Vec::new();
+    /// Writer::write_chains(chains.values(), &mut out, ChainOrder::Id).unwrap();
+    ///
+    /// assert_eq!(&out[..], &line[..]);
+    /// ```
+    pub fn write_chains<I, B, W>(chains: I, mut out: W, order: ChainOrder) -> Result<()>
+    where
+        I: IntoIterator<Item = B>,
+        B: Borrow<Chain>,
+        W: Write,
+    {
+        if order == ChainOrder::Unsorted {
+            for chain in chains {
+                Self::write_chain(chain.borrow(), &mut out)?;
+            }
+            return Ok(());
+        }
+
+        let mut chains: Vec<B> = chains.into_iter().collect();
+        chains.sort_by(|a, b| order.compare(a.borrow(), b.borrow()));
+        for chain in &chains {
+            Self::write_chain(chain.borrow(), &mut out)?;
+        }
+        Ok(())
+    }
+
+    // Private writer of a single chain; malformed lazily parsed blocks fail here
+    // instead of being written without blocks
+    fn write_chain<W: Write>(chain: &Chain, out: &mut W) -> Result<()> {
+        chain.records()?;
+        out.write_all(chain.to_string().as_bytes())?;
+        Ok(())
+    }
+
+    /// Write metadata as `#` comment lines to any writer
+    ///
+    /// # Arguments
+    /// * `metadata` - The metadata of a ChainMap
+    /// * `out` - A writer
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub fn write_metadata<W: Write>(metadata: &Metadata, mut out: W) -> Result<()> {
+        for line in metadata.lines() {
+            writeln!(out, "#{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Write a ChainMap as a plain-text .chain stream to any writer, optionally
+    /// compressed, with its metadata as leading `#` comment lines
+    ///
+    /// # Arguments
+    /// * `map` - A ChainMap object
+    /// * `out` - A writer, e.g. stdout, a file or a `Vec<u8>`
+    /// * `order` - The order in which chains are written
+    /// * `compression` - The compression format of the output
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::compress::Compression;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let data = b"#source: hg38.mm39\r\nchain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\r\n10\r\n";
+    /// let mut chains = Reader::from_bytes(data).unwrap();
+    /// chains.metadata.insert("filtered", "score >= 10");
+    ///
+    /// let mut out: Vec<u8> = Vec::new();
+    /// Writer::write_map(&chains, &mut out, ChainOrder::Id, Compression::None).unwrap();
+    ///
+    /// assert_eq!(
+    ///     &out[..],
+    ///     &b"#source: hg38.mm39\n#filtered: score >= 10\nchain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n"[..]
+    /// );
+    /// ```
+    pub fn write_map<W: Write>(map: &ChainMap, out: W, order: ChainOrder, compression: Compression) -> Result<()> {
+        compression
+            .compress(out, |w| {
+                Self::write_metadata(&map.metadata, &mut *w)?;
+                Self::write_chains(map.values(), w, order)
+            })?
+            .flush()?;
         Ok(())
     }
+
+    /// Write a ChainMap as a plain-text .chain file, with its metadata as leading `#` comment lines
+    ///
+    /// # Arguments
+    /// * `map` - A ChainMap object
+    /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let data = b"#source: hg38.mm39\nchain 10 chr1 100 + 0 10 chr2 100 + 0 10 1\n10\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join(format!("chaintools-doc-map-{}.chain", std::process::id()));
+    /// Writer::map_to_chain(&chains, &path, ChainOrder::Id).unwrap();
+    /// assert_eq!(std::fs::read(&path).unwrap(), &data[..]);
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn map_to_chain<T: AsRef<Path> + Debug>(map: &ChainMap, path: T, order: ChainOrder) -> Result<()> {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::write_map(map, BufWriter::new(file), order, Compression::None)
+    }
+
+    /// Write a ChainMap as a gzip-compressed plain-text .chain file, with its metadata
+    /// as leading `#` comment lines
+    ///
+    /// # Arguments
+    /// * `map` - A ChainMap object
+    /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{reader::Reader, writer::Writer};
+    ///
+    /// let chains = Reader::from_file("/path/to/input.chain").unwrap();
+    /// Writer::map_to_chain_gz(&chains, "/path/to/output.chain.gz", ChainOrder::Reference).unwrap();
+    /// ```
+    pub fn map_to_chain_gz<T: AsRef<Path> + Debug>(map: &ChainMap, path: T, order: ChainOrder) -> Result<()> {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::write_map(map, BufWriter::new(file), order, Compression::Gzip)
+    }
+
+    /// Write a ChainMap as a BGZF-compressed plain-text .chain file, with its metadata
+    /// as leading `#` comment lines
+    ///
+    /// # Arguments
+    /// * `map` - A ChainMap object
+    /// * `path` - A path to the output file
+    /// * `order` - The order in which chains are written
+    ///
+    /// # Returns
+    /// * Result<()>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::cmap::map::ChainOrder;
+    /// use chaintools::io::{indexer::BinaryIndex, reader::Reader, writer::Writer};
+    ///
+    /// let chains = Reader::from_file("/path/to/input.chain").unwrap();
+    /// Writer::map_to_chain_bgzf(&chains, "/path/to/output.chain.gz", ChainOrder::Reference).unwrap();
+    /// BinaryIndex::index("/path/to/output.chain.gz").unwrap();
+    /// ```
+    pub fn map_to_chain_bgzf<T: AsRef<Path> + Debug>(map: &ChainMap, path: T, order: ChainOrder) -> Result<()> {
+        let file = File::create(&path).with_context(|| format!("Failed to create file {:?}", path))?;
+        Self::write_map(map, BufWriter::new(file), order, Compression::Bgzf)
+    }
 }
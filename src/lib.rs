@@ -11,8 +11,9 @@
 //! The entrypoint for reading genomic chain files in the 'chaintools' Rust library is through
 //! the Reader struct, offering methods like [`Reader::from_file`] to create a reader from a file path
 //! and return a [`Result`] with a [`ChainMap`], [`Reader::from_bytes`] to create a reader from a byte slice,
-//! [`Reader::from_bin`] to create a reader from a binary file path, and [`Reader::load_chain`] to load a
-//! specific chain from a binary file using a chain ID. Internally, the Reader struct handles file
+//! [`Reader::from_bin`] to create a reader from a binary file path, and [`Reader::load_chain`] and
+//! [`Reader::load_chains`] to load specific chains from a binary file using chain IDs, decoding only
+//! the requested chains. Internally, the Reader struct handles file
 //! operations, parsing byte data efficiently, and utilizes Rust's standard library for file I/O
 //! and parallel processing for parsing and filtering chain data, providing a comprehensive interface
 //! for working with genomic chain files in various formats within Rust.
@@ -25,6 +26,44 @@
 //! let reader: ChainMap = Reader::from_file("path/to/chainfile.chain")?;
 //! ```
 //!
+//! Any byte source (stdin, a pipe, an in-memory cursor) can be read with [`Reader::from_reader`];
+//! gzip and BGZF input, as well as zstd, bzip2 and xz behind the `zstd`, `bzip2` and `xz` features,
+//! are recognized from their magic bytes rather than from file extensions.
+//! Chains are keyed by [`cmap::chain::ChainId`], a 64-bit ID shared by maps, indices, extraction,
+//! binary containers and errors.
+//! Readers tolerate CRLF line endings, tab-separated header fields, runs of blank lines and
+//! `#` comment lines; the comments are kept as [`cmap::map::Metadata`] on the ChainMap.
+//!
+//! When only chain headers are needed (e.g. filtering by score, size or chromosome),
+//! [`Reader::from_file_lazy`] keeps alignment blocks as raw text and parses them the first
+//! time they are accessed; malformed blocks are reported by [`cmap::align::Alignment::try_records`]
+//! and by the writers.
+//!
+//! For files too large to be kept in memory, [`ChainReader`] streams plain or gzipped
+//! chain files and yields one [`Chain`] at a time, reusing the same header and alignment parsers.
+//!
+//! # Examples
+//! ```rust,no_run
+//! # fn main() -> anyhow::Result<()> {
+//! use chaintools::io::reader::ChainReader;
+//!
+//! for chain in ChainReader::from_file("path/to/chainfile.chain.gz")? {
+//!     println!("{}", chain?.header());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Parsing, lookup and I/O failures are reported as [`error::ChaintoolsError`] values wrapped in
+//! [`anyhow::Error`]; downcasting them gives access to the line number, byte offset and chain ID
+//! of a malformed header or alignment block, or to the ID of a chain missing from a file or index.
+//! Readers stop at the first malformed chain by default; [`Reader::from_file_with`] and its siblings
+//! take [`io::parse::ParseOptions`] to skip or repair such chains instead, and return a
+//! [`io::parse::ParseReport`] listing every chain that was not read as it is. Of chains sharing an
+//! ID the first one is kept by default and the others are reported as skipped; a
+//! [`cmap::map::MergePolicy`] refuses them, keeps the highest-scoring one, or renumbers the
+//! repeats and reports the ID mapping.
+//!
 //! ## Operations over chain files
 //!
 //! The ChainMap struct provides a variety of methods for performing operations on chain files,
@@ -35,6 +74,15 @@
 //! filtering methods like [`ChainMap::filter_id`] and [`ChainMap::filter_score`] to filter chains by ID
 //! and score, respectively. This set of methods is inteded to grow over time as more operations are
 //! needed for working with genomic chain files in Rust (e.g., merging, splitting, etc).
+//! [`ChainMap::validate`] checks every chain for internal consistency (header spans against the
+//! alignment, coordinates within sequence sizes, strands, trailing gaps and empty blocks) and
+//! lists the findings per chain, e.g. to gate inputs produced by third-party aligners.
+//! As a ChainMap is a hash map, [`ChainMap::sorted`] iterates over its chains in a given
+//! [`ChainOrder`] without cloning them, while [`ChainMap::into_sorted_vec`] and
+//! [`ChainMap::into_sorted`] give owned, ordered collections of chains.
+//! [`ChainMap::merge`] combines chain maps, resolving colliding IDs with a
+//! [`cmap::map::MergePolicy`] and keeping the source tags set by [`ChainMap::with_source`],
+//! while [`ChainMap::intersection`] and [`ChainMap::difference`] match chains by ID or by content.
 //!
 //! On top of these operations, the Chain struct provides a simple interface for working with individual
 //! chains, allowing users to access chain data like ID, score, and blocks, as well as perform basic string
@@ -55,12 +103,47 @@
 //! let chain: Chain = reader.get(10);
 //! ```
 //!
+//! ## Lifting over genomic records
+//!
+//! [`liftover::Liftover`] indexes all chains of a ChainMap by reference chromosome and lifts
+//! records to the query assembly with UCSC `liftOver` semantics: a record is lifted when a single
+//! chain aligns at least [`liftover::LiftOptions::min_match`] of its bases.
+//! [`liftover::Liftover::lift_bed_file`] lifts a BED file in parallel over chromosomes and writes
+//! lifted and unmapped records to separate files. Unmapped records are annotated with an
+//! [`liftover::UnmappedReason`] (deleted, split, partially deleted, duplicated, no chain on the
+//! chromosome or enclosed in a chain gap), also listed in the returned [`liftover::LiftReport`].
+//! [`liftover::Liftover::lift_vcf_file`] lifts plain or gzipped VCF files through aligned blocks
+//! only, rewriting `##contig` lines for the query assembly and flagging records whose alleles
+//! need reverse-complementing; rejected records keep their reason as `FILTER`.
+//! [`liftover::Liftover::lift_gff_file`] lifts GFF3 and GTF annotations, re-deriving gene and
+//! transcript bounds from their lifted parts and dropping or flagging features split across
+//! chains or chromosomes according to [`liftover::SplitPolicy`]. BED12 records keep their exon
+//! structure: [`cmap::chain::Chain::project_bed12`] projects every block and the thick coordinates
+//! through a chain, reorders blocks for chains on the negative query strand and lists the blocks
+//! that could not be mapped.
+//!
+//! # Examples
+//! ```rust,no_run
+//! use chaintools::io::reader::Reader;
+//! use chaintools::liftover::{LiftOptions, Liftover};
+//!
+//! let chains = Reader::from_file("path/to/hg38ToMm10.over.chain.gz").unwrap();
+//! let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+//! liftover.lift_bed_file("path/to/in.bed", "path/to/lifted.bed", "path/to/unmapped.bed").unwrap();
+//! ```
+//!
 //! ## Writing chain files
 //!
 //! The Writer struct provides a simple interface for writing genomic chain files in Rust, offering
-//! a discrete set of methods in this release, such as [`Writer::to_bin`] to write a encoded chain
-//! file and [`Writer::to_bin_gz`] to write an encoded and gz compressed chain file in cases were
-//! the size of the file is a concern.
+//! a discrete set of methods in this release, such as [`Writer::to_bin`] to write a seekable binary
+//! container of independently and compactly encoded chains (see [`io::codec`]) and [`Writer::to_bin_gz`] to write an encoded and gz compressed chain file in cases were
+//! the size of the file is a concern. Chains can also be written back as standard UCSC .chain
+//! files with [`Writer::to_chain`] and [`Writer::to_chain_gz`], ordered by ID, by score or by
+//! reference or query position according to [`ChainOrder`], or streamed to any writer in any supported
+//! compression format with [`Writer::to_writer`]. [`Writer::write_map`] and [`Writer::map_to_chain`]
+//! write a whole ChainMap, re-emitting its metadata as leading `#` comment lines. Chain files larger than memory are merged
+//! and sorted with [`io::sort::MergeSort`], which spills sorted runs to temporary files and k-way
+//! merges them into a plain or BGZF output, optionally renumbering chains.
 //!
 //! # Examples
 //! ```rust
@@ -70,14 +153,17 @@
 //!
 //! let chains: ChainMap = Reader::from_file("path/to/chainfile.chain")?;
 //! let filt_chains = chains.filter(|c| c.score > 1000);
-//! Writer::to_bin(&filt_chains, "path/to/chainfile.chain");
+//! Writer::to_bin(filt_chains.values(), "path/to/chainfile.bin");
 //! ```
 
 #![warn(rust_2021_compatibility)]
 #![warn(rust_2018_idioms)]
 
 pub mod cmap;
+pub mod error;
 pub mod io;
+#[cfg(feature = "read")]
+pub mod liftover;
 
 pub use crate::io::*;
 pub use crate::cmap::*;
@@ -0,0 +1,680 @@
+/*!
+Contains the liftover of genomic records across a whole chain file.
+
+Unlike [`crate::cmap::chain::Chain::map_through_`], which projects intervals through a single
+chain chosen by the caller, a [`Liftover`] indexes every chain of a [`ChainMap`] by reference
+chromosome and finds the chains overlapping each record itself, following UCSC `liftOver`.
+*/
+
+pub mod bed;
+pub mod gff;
+pub mod vcf;
+
+use anyhow::Context;
+use fxhash::FxHashMap;
+use rayon::prelude::*;
+use std::cmp::{max, min};
+use std::fmt::{self, Display};
+use std::io::{BufRead, BufReader, Read};
+
+use crate::cmap::chain::{Chain, ChainId};
+use crate::cmap::map::ChainMap;
+use crate::io::compress::Compression;
+
+/// Default minimum ratio of bases that must remap, as UCSC `liftOver -minMatch`
+pub const DEFAULT_MIN_MATCH: f64 = 0.95;
+
+/// Number of input lines lifted at once by the file lifters, bounding their memory use
+pub const BATCH_LINES: usize = 1 << 16;
+
+/// How features whose parts land on different chains or chromosomes are lifted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitPolicy {
+    /// Leave the feature and all its parts unmapped
+    #[default]
+    Drop,
+    /// Lift the parts on their own and the feature on the chain holding most of them, and flag them
+    Flag,
+}
+
+/// Options controlling a liftover
+///
+/// # Example
+/// ```
+/// use chaintools::liftover::LiftOptions;
+///
+/// let options = LiftOptions::new().with_min_match(0.5);
+/// assert_eq!(options.min_match, 0.5);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct LiftOptions {
+    /// Minimum ratio of the bases of a record covered by aligned blocks of a chain
+    pub min_match: f64,
+    /// Leave records enclosed in a chain gap unmapped rather than lifting them to the
+    /// facing gap of the query, as the flag of the same name of [`crate::cmap::chain::Chain::map_through_`]
+    pub ignore_undefined: bool,
+    /// How features split across chains or chromosomes are lifted
+    pub split: SplitPolicy,
+}
+
+impl Default for LiftOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LiftOptions {
+    /// Create liftover options with the UCSC default minimum match of 0.95,
+    /// leaving records enclosed in chain gaps and split features unmapped
+    ///
+    /// # Returns
+    /// * LiftOptions
+    pub fn new() -> Self {
+        Self {
+            min_match: DEFAULT_MIN_MATCH,
+            ignore_undefined: true,
+            split: SplitPolicy::Drop,
+        }
+    }
+
+    /// Set the minimum ratio of bases that must remap
+    ///
+    /// # Arguments
+    /// * `min_match` - A ratio between 0 and 1
+    ///
+    /// # Returns
+    /// * LiftOptions
+    pub fn with_min_match(mut self, min_match: f64) -> Self {
+        self.min_match = min_match;
+        self
+    }
+
+    /// Set whether records enclosed in a chain gap are left unmapped
+    ///
+    /// # Arguments
+    /// * `ignore_undefined` - Leave such records unmapped if true, lift them to the query gap otherwise
+    ///
+    /// # Returns
+    /// * LiftOptions
+    pub fn with_ignore_undefined(mut self, ignore_undefined: bool) -> Self {
+        self.ignore_undefined = ignore_undefined;
+        self
+    }
+
+    /// Set how features split across chains or chromosomes are lifted
+    ///
+    /// # Arguments
+    /// * `split` - A split policy
+    ///
+    /// # Returns
+    /// * LiftOptions
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, SplitPolicy, UnmappedReason};
+    ///
+    /// let chains = Reader::from_bytes(b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1200 1\n200\n\n\
+    /// chain 100 chr1 1000 + 400 600 chrB 3000 + 0 200 2\n200\n\n").unwrap();
+    /// let gff = b"chr1\tsrc\tgene\t101\t600\t.\t+\t.\tID=g1\n\
+    /// chr1\tsrc\tmRNA\t101\t600\t.\t+\t.\tID=t1;Parent=g1\n\
+    /// chr1\tsrc\texon\t111\t140\t.\t+\t.\tParent=t1\n\
+    /// chr1\tsrc\texon\t451\t500\t.\t+\t.\tParent=t1\n";
+    ///
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    /// let report = liftover.lift_gff(&gff[..], Vec::new(), Vec::new()).unwrap();
+    /// assert_eq!(report.unmapped.len(), 4);
+    /// assert!(report.unmapped.iter().all(|x| x.reason == UnmappedReason::Split));
+    ///
+    /// // the transcript and the gene stay on the chain holding most of the exons
+    /// let liftover = Liftover::new(&chains, LiftOptions::new().with_split(SplitPolicy::Flag)).unwrap();
+    /// let mut lifted = Vec::new();
+    /// let report = liftover.lift_gff(&gff[..], &mut lifted, Vec::new()).unwrap();
+    /// assert_eq!(report.lifted, 4);
+    /// assert_eq!(
+    ///     String::from_utf8(lifted).unwrap(),
+    ///     "chrB\tsrc\tgene\t51\t100\t.\t+\t.\tID=g1;lift_split=true\n\
+    ///      chrB\tsrc\tmRNA\t51\t100\t.\t+\t.\tID=t1;Parent=g1;lift_split=true\n\
+    ///      chrA\tsrc\texon\t1011\t1040\t.\t+\t.\tParent=t1;lift_split=true\n\
+    ///      chrB\tsrc\texon\t51\t100\t.\t+\t.\tParent=t1;lift_split=true\n"
+    /// );
+    /// ```
+    pub fn with_split(mut self, split: SplitPolicy) -> Self {
+        self.split = split;
+        self
+    }
+}
+
+/// Why a record could not be lifted
+///
+/// Displayed as the `#` annotation lines of unmapped files, following UCSC `liftOver`
+/// wording where it has an equivalent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnmappedReason {
+    /// Chains cover the chromosome but none overlaps the record
+    Deleted,
+    /// No chain covers enough of the record, but several chains cover part of it
+    Split,
+    /// A single chain covers part of the record, below the minimum match
+    PartiallyDeleted,
+    /// Several chains cover enough of the record
+    Duplicated,
+    /// No chain starts on the chromosome of the record
+    NoChain,
+    /// The record is enclosed in a chain gap and [`LiftOptions::ignore_undefined`] is set
+    InGap,
+    /// The record could not be parsed
+    Malformed,
+}
+
+impl UnmappedReason {
+    /// All reasons, in declaration order
+    pub const ALL: [UnmappedReason; 7] = [
+        UnmappedReason::Deleted,
+        UnmappedReason::Split,
+        UnmappedReason::PartiallyDeleted,
+        UnmappedReason::Duplicated,
+        UnmappedReason::NoChain,
+        UnmappedReason::InGap,
+        UnmappedReason::Malformed,
+    ];
+
+    /// Get a short identifier of the reason, usable as a VCF `FILTER` value
+    ///
+    /// # Returns
+    /// * &str
+    pub fn id(&self) -> &'static str {
+        match self {
+            UnmappedReason::Deleted => "Deleted",
+            UnmappedReason::Split => "Split",
+            UnmappedReason::PartiallyDeleted => "PartiallyDeleted",
+            UnmappedReason::Duplicated => "Duplicated",
+            UnmappedReason::NoChain => "NoChain",
+            UnmappedReason::InGap => "InGap",
+            UnmappedReason::Malformed => "Malformed",
+        }
+    }
+}
+
+impl Display for UnmappedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            UnmappedReason::Deleted => "Deleted in new",
+            UnmappedReason::Split => "Split in new",
+            UnmappedReason::PartiallyDeleted => "Partially deleted in new",
+            UnmappedReason::Duplicated => "Duplicated in new",
+            UnmappedReason::NoChain => "No chain on chromosome",
+            UnmappedReason::InGap => "Enclosed in a chain gap",
+            UnmappedReason::Malformed => "Malformed record",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A record that could not be lifted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappedRecord {
+    /// 1-based line number of the record in the input
+    pub line: u64,
+    /// The record as read, without its line terminator
+    pub record: String,
+    pub reason: UnmappedReason,
+    /// 0-based indices of the blocks of a BED12 record that could not be mapped,
+    /// empty for other records
+    pub blocks: Vec<usize>,
+}
+
+impl Display for UnmappedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if !self.blocks.is_empty() {
+            let blocks: Vec<String> = self.blocks.iter().map(|x| x.to_string()).collect();
+            write!(f, " (unmapped blocks {})", blocks.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of a finished liftover
+///
+/// Unmapped records are listed in input order, each with the reason it could not
+/// be lifted, as in the annotated unmapped output.
+#[derive(Debug, Clone, Default)]
+pub struct LiftReport {
+    /// Number of records lifted
+    pub lifted: u64,
+    pub unmapped: Vec<UnmappedRecord>,
+}
+
+impl LiftReport {
+    /// Count the unmapped records for a given reason
+    ///
+    /// # Arguments
+    /// * `reason` - An unmapped reason
+    ///
+    /// # Returns
+    /// * usize
+    pub fn count(&self, reason: UnmappedReason) -> usize {
+        self.unmapped.iter().filter(|x| x.reason == reason).count()
+    }
+}
+
+impl Display for LiftReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} records lifted, {} records unmapped", self.lifted, self.unmapped.len())?;
+        for record in &self.unmapped {
+            write!(f, "\nline {}: {}", record.line, record)?;
+        }
+        Ok(())
+    }
+}
+
+/// A record lifted to the query assembly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lift<'a> {
+    /// Query chromosome
+    pub chr: &'a str,
+    /// 0-based start on the positive strand of the query chromosome
+    pub start: u64,
+    /// Exclusive end on the positive strand of the query chromosome
+    pub end: u64,
+    /// `-` if the record changes orientation, `+` otherwise
+    pub strand: char,
+    /// ID of the chain the record was lifted through
+    pub chain_id: ChainId,
+}
+
+/// Lines of a plain or compressed input, read in batches so that records are lifted
+/// without holding the whole input in memory
+pub(crate) struct LineBatches<'r> {
+    reader: Box<dyn BufRead + 'r>,
+    /// Name of the input format, for error messages
+    format: &'static str,
+    /// 1-based number of the last line read
+    number: usize,
+    /// The line read past the end of the previous batch
+    pending: Option<(usize, String)>,
+}
+
+impl<'r> LineBatches<'r> {
+    /// Open a plain or compressed input
+    ///
+    /// # Arguments
+    /// * `input` - A reader
+    /// * `format` - Name of the input format, for error messages
+    ///
+    /// # Returns
+    /// * Result<LineBatches>
+    pub(crate) fn new<R: Read + 'r>(input: R, format: &'static str) -> anyhow::Result<Self> {
+        let reader = Compression::decompress(BufReader::new(input))?;
+        Ok(Self { reader, format, number: 0, pending: None })
+    }
+
+    /// Read the next batch of lines, without their terminators, along with their 1-based numbers
+    ///
+    /// A batch ends after `limit` lines, or before the first line whose key differs from
+    /// the key of the previous keyed line of the batch; lines without key never end a batch.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of lines in the batch
+    /// * `key` - A function giving the key of a line, e.g. the chromosome of a record
+    ///
+    /// # Returns
+    /// * Result<Vec<(usize, String)>> - empty once the input is exhausted
+    pub(crate) fn read_batch<F>(&mut self, limit: usize, key: F) -> anyhow::Result<Vec<(usize, String)>>
+    where
+        F: Fn(&str) -> Option<&str>,
+    {
+        let mut batch: Vec<(usize, String)> = Vec::new();
+        let mut last: Option<String> = None;
+        while batch.len() < limit {
+            let (number, line) = match self.pending.take() {
+                Some(pending) => pending,
+                None => {
+                    let mut line = String::new();
+                    let read = self
+                        .reader
+                        .read_line(&mut line)
+                        .with_context(|| format!("Failed to read {} data", self.format))?;
+                    if read == 0 {
+                        break;
+                    }
+                    self.number += 1;
+                    let len = line.trim_end_matches('\n').trim_end_matches('\r').len();
+                    line.truncate(len);
+                    (self.number, line)
+                }
+            };
+            match (key(&line), last.as_deref()) {
+                (Some(key), Some(last)) if key != last => {
+                    self.pending = Some((number, line));
+                    break;
+                }
+                (Some(key), None) => last = Some(key.to_string()),
+                _ => {}
+            }
+            batch.push((number, line));
+        }
+        Ok(batch)
+    }
+}
+
+/// Chains of a ChainMap indexed by reference chromosome for liftover
+///
+/// # Example
+/// ```
+/// use chaintools::io::reader::Reader;
+/// use chaintools::liftover::{LiftOptions, Liftover};
+///
+/// let data = b"chain 100 chr1 1000 + 100 300 chrA 5000 - 1000 1210 1\n50\t10\t20\n140\n\n";
+/// let chains = Reader::from_bytes(data).unwrap();
+/// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+///
+/// let lift = liftover.lift("chr1", 110, 130).unwrap();
+/// assert_eq!((lift.chr, lift.start, lift.end, lift.strand), ("chrA", 3970, 3990, '-'));
+/// assert!(liftover.lift("chr1", 150, 160).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Liftover<'a> {
+    chroms: FxHashMap<&'a str, ChromChains<'a>>,
+    chains: FxHashMap<ChainId, &'a Chain>,
+    query: Vec<(&'a str, u64)>,
+    options: LiftOptions,
+}
+
+// Private chains of a reference chromosome, sorted by reference start
+#[derive(Debug, Clone, Default)]
+struct ChromChains<'a> {
+    chains: Vec<&'a Chain>,
+    max_span: u64,
+}
+
+// Private projection of a record through one chain
+enum Hit<'a> {
+    Mapped(Lift<'a>),
+    Partial,
+    Gap(Lift<'a>),
+}
+
+impl<'a> Liftover<'a> {
+    /// Index the chains of a ChainMap by reference chromosome
+    ///
+    /// Alignments of lazily read chains are parsed here, so that a malformed block
+    /// fails once instead of leaving records lifted through it unmapped.
+    ///
+    /// # Arguments
+    /// * `chains` - A ChainMap
+    /// * `options` - Liftover options
+    ///
+    /// # Returns
+    /// * Result<Liftover> - an error naming the first chain whose alignment cannot be parsed
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover};
+    ///
+    /// let data = b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1210 1\n50\tx\t20\n140\n\n";
+    /// let chains = Reader::parse_lazy(data).unwrap();
+    /// assert!(Liftover::new(&chains, LiftOptions::new()).is_err());
+    /// ```
+    pub fn new(chains: &'a ChainMap, options: LiftOptions) -> anyhow::Result<Self> {
+        chains.values().par_bridge().try_for_each(|chain| chain.records().map(|_| ()))?;
+        let mut chroms: FxHashMap<&'a str, ChromChains<'a>> = FxHashMap::default();
+        let mut query: Vec<(&'a str, u64)> = Vec::new();
+        for chain in chains.values() {
+            query.push((chain.query.chr.as_str(), chain.query.size));
+            let entry = chroms.entry(chain.refs.chr.as_str()).or_default();
+            entry.max_span = max(entry.max_span, chain.refs.end - chain.refs.start);
+            entry.chains.push(chain);
+        }
+        for entry in chroms.values_mut() {
+            entry
+                .chains
+                .sort_unstable_by_key(|chain| (chain.refs.start, chain.refs.end, chain.id));
+        }
+        query.sort_unstable();
+        query.dedup_by_key(|(chr, _)| *chr);
+        let chains = chains.values().map(|chain| (chain.id, chain)).collect();
+        Ok(Self { chroms, chains, query, options })
+    }
+
+    /// Get the liftover options
+    ///
+    /// # Returns
+    /// * &LiftOptions
+    pub fn options(&self) -> &LiftOptions {
+        &self.options
+    }
+
+    /// Get a chain by ID, e.g. the chain a record was lifted through
+    ///
+    /// # Arguments
+    /// * `id` - A chain ID
+    ///
+    /// # Returns
+    /// * Option<&Chain>
+    pub fn chain(&self, id: &ChainId) -> Option<&'a Chain> {
+        self.chains.get(id).copied()
+    }
+
+    /// Get the query chromosomes of the chains and their sizes, sorted by name
+    ///
+    /// # Returns
+    /// * &[(&str, u64)]
+    pub fn query_sizes(&self) -> &[(&'a str, u64)] {
+        &self.query
+    }
+
+    /// Lift a reference interval to the query assembly
+    ///
+    /// As UCSC `liftOver`, an interval is lifted if exactly one chain covers at least
+    /// `min_match` of its bases with aligned blocks; the lifted interval spans from the
+    /// first to the last aligned base of the interval. Empty intervals are lifted if they
+    /// lie within or at the edge of an aligned block.
+    ///
+    /// # Arguments
+    /// * `chr` - Reference chromosome
+    /// * `start` - 0-based start
+    /// * `end` - Exclusive end
+    ///
+    /// # Returns
+    /// * Option<Lift> - `None` if the interval cannot be lifted, see [`Liftover::try_lift`]
+    pub fn lift(&self, chr: &str, start: u64, end: u64) -> Option<Lift<'a>> {
+        self.try_lift(chr, start, end).ok()
+    }
+
+    /// Lift a reference interval to the query assembly, telling why it cannot be lifted
+    ///
+    /// # Arguments
+    /// * `chr` - Reference chromosome
+    /// * `start` - 0-based start
+    /// * `end` - Exclusive end
+    ///
+    /// # Returns
+    /// * Result<Lift, UnmappedReason>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, UnmappedReason};
+    ///
+    /// let data = b"chain 100 chr1 1000 + 100 300 chrA 5000 + 1000 1210 1\n50\t10\t20\n140\n\n";
+    /// let chains = Reader::from_bytes(data).unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    ///
+    /// assert_eq!(liftover.try_lift("chr1", 150, 160), Err(UnmappedReason::InGap));
+    /// assert_eq!(liftover.try_lift("chr1", 140, 170), Err(UnmappedReason::PartiallyDeleted));
+    /// assert_eq!(liftover.try_lift("chr1", 400, 500), Err(UnmappedReason::Deleted));
+    /// assert_eq!(liftover.try_lift("chr2", 100, 200), Err(UnmappedReason::NoChain));
+    ///
+    /// let liftover = Liftover::new(&chains, LiftOptions::new().with_ignore_undefined(false)).unwrap();
+    /// let lift = liftover.try_lift("chr1", 150, 160).unwrap();
+    /// assert_eq!((lift.start, lift.end), (1050, 1070));
+    /// ```
+    pub fn try_lift(&self, chr: &str, start: u64, end: u64) -> Result<Lift<'a>, UnmappedReason> {
+        self.lift_within(chr, start, end, self.options.ignore_undefined)
+    }
+
+    /// Lift a reference interval, overriding whether chain gaps are left undefined
+    ///
+    /// # Arguments
+    /// * `chr` - Reference chromosome
+    /// * `start` - 0-based start
+    /// * `end` - Exclusive end
+    /// * `ignore_undefined` - Leave intervals enclosed in chain gaps unmapped
+    ///
+    /// # Returns
+    /// * Result<Lift, UnmappedReason>
+    pub(crate) fn lift_within(
+        &self,
+        chr: &str,
+        start: u64,
+        end: u64,
+        ignore_undefined: bool,
+    ) -> Result<Lift<'a>, UnmappedReason> {
+        if !self.chroms.contains_key(chr) {
+            return Err(UnmappedReason::NoChain);
+        }
+        let mut lifted = None;
+        let mut gap = None;
+        let mut partial = 0;
+        for chain in self.overlapping(chr, start, end) {
+            match self.map_through(chain, start, end) {
+                Hit::Mapped(_) if lifted.is_some() => return Err(UnmappedReason::Duplicated),
+                Hit::Mapped(lift) => lifted = Some(lift),
+                Hit::Partial => partial += 1,
+                Hit::Gap(lift) => gap = gap.or(Some(lift)),
+            }
+        }
+        match (lifted, partial, gap) {
+            (Some(lift), _, _) => Ok(lift),
+            (None, 1, _) => Err(UnmappedReason::PartiallyDeleted),
+            (None, 0, Some(_)) if ignore_undefined => Err(UnmappedReason::InGap),
+            (None, 0, Some(lift)) => Ok(lift),
+            (None, 0, None) => Err(UnmappedReason::Deleted),
+            (None, _, _) => Err(UnmappedReason::Split),
+        }
+    }
+
+    /// Lift a reference interval through a given chain only
+    ///
+    /// As for VCF records, intervals enclosed in a gap of the chain are left unmapped.
+    ///
+    /// # Arguments
+    /// * `id` - ID of the chain, e.g. the chain another part of the same record was lifted through
+    /// * `start` - 0-based start
+    /// * `end` - Exclusive end
+    ///
+    /// # Returns
+    /// * Result<Lift, UnmappedReason>
+    pub(crate) fn lift_through(&self, id: ChainId, start: u64, end: u64) -> Result<Lift<'a>, UnmappedReason> {
+        let chain = self.chain(&id).ok_or(UnmappedReason::NoChain)?;
+        if start < chain.refs.start || end > chain.refs.end {
+            return Err(UnmappedReason::Deleted);
+        }
+        match self.map_through(chain, start, end) {
+            Hit::Mapped(lift) => Ok(lift),
+            Hit::Partial => Err(UnmappedReason::PartiallyDeleted),
+            Hit::Gap(_) => Err(UnmappedReason::InGap),
+        }
+    }
+
+    /// Lift records in parallel over reference chromosomes
+    ///
+    /// # Arguments
+    /// * `records` - A slice of records
+    /// * `ignore_undefined` - Leave records enclosed in chain gaps unmapped
+    /// * `interval` - A function giving the reference chromosome, start and end of a record
+    ///
+    /// # Returns
+    /// * Vec<Result<Lift, UnmappedReason>> - the lift of every record, in input order
+    pub(crate) fn lift_records<T, F>(
+        &self,
+        records: &[T],
+        ignore_undefined: bool,
+        interval: F,
+    ) -> Vec<Result<Lift<'a>, UnmappedReason>>
+    where
+        T: Sync,
+        F: Fn(&T) -> (&str, u64, u64) + Sync,
+    {
+        let mut chroms: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+        for (i, record) in records.iter().enumerate() {
+            chroms.entry(interval(record).0).or_default().push(i);
+        }
+        let mut lifts: Vec<(usize, Result<Lift<'a>, UnmappedReason>)> = chroms
+            .into_par_iter()
+            .flat_map_iter(|(_, indices)| {
+                indices.into_iter().map(|i| {
+                    let (chr, start, end) = interval(&records[i]);
+                    (i, self.lift_within(chr, start, end, ignore_undefined))
+                })
+            })
+            .collect();
+
+        lifts.sort_unstable_by_key(|(i, _)| *i);
+        lifts.into_iter().map(|(_, lift)| lift).collect()
+    }
+
+    // Private iterator over the chains overlapping a reference interval
+    fn overlapping(&self, chr: &str, start: u64, end: u64) -> impl Iterator<Item = &'a Chain> + '_ {
+        let (chains, lower) = match self.chroms.get(chr) {
+            Some(entry) => (&entry.chains[..], start.saturating_sub(entry.max_span)),
+            None => (&[][..], 0),
+        };
+        let first = chains.partition_point(|chain| chain.refs.start < lower);
+        chains[first..]
+            .iter()
+            .take_while(move |chain| chain.refs.start < end || chain.refs.start == start)
+            .filter(move |chain| chain.refs.end > start || (start == end && chain.refs.end == start))
+            .copied()
+    }
+
+    // Private projection of a reference interval through a chain
+    fn map_through(&self, chain: &'a Chain, start: u64, end: u64) -> Hit<'a> {
+        // the query gap following the last block passed
+        let mut gap = (chain.query.start, chain.query.start);
+        let mut aligned: u64 = 0;
+        let mut span: Option<(u64, u64)> = None;
+        for block in chain.walk_blocks() {
+            if block.r_start > end || (block.r_start == end && start < end) {
+                break;
+            }
+            let (lo, hi) = (max(start, block.r_start), min(end, block.r_end));
+            if lo < hi || (start == end && lo == hi) {
+                aligned += hi - lo;
+                let (q_lo, q_hi) = block.project(lo, hi);
+                span = Some(span.map_or((q_lo, q_hi), |(s, _)| (s, q_hi)));
+                if start == end {
+                    break;
+                }
+            }
+            gap = (block.q_end, block.q_end + block.record.dq as u64);
+        }
+
+        // overlapping chains without aligned bases enclose the interval in one of their gaps
+        let Some((q_lo, q_hi)) = span else {
+            return Hit::Gap(Self::to_lift(chain, gap.0, gap.1));
+        };
+        if (aligned as f64) < (end - start) as f64 * self.options.min_match {
+            return Hit::Partial;
+        }
+        Hit::Mapped(Self::to_lift(chain, q_lo, q_hi))
+    }
+
+    // Private conversion of query coordinates of a chain to a lift on the positive strand
+    fn to_lift(chain: &'a Chain, q_lo: u64, q_hi: u64) -> Lift<'a> {
+        let (start, end) = match chain.query.strand {
+            '-' => (chain.query.size - q_hi, chain.query.size - q_lo),
+            _ => (q_lo, q_hi),
+        };
+        Lift {
+            chr: chain.query.chr.as_str(),
+            start,
+            end,
+            strand: chain.query.strand,
+            chain_id: chain.id,
+        }
+    }
+}
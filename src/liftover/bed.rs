@@ -0,0 +1,318 @@
+use anyhow::{Context, Result};
+use cubiculum::extract::extract::to_line;
+use cubiculum::structs::structs::BedEntry;
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::liftover::{Lift, LineBatches, LiftReport, Liftover, UnmappedReason, UnmappedRecord, BATCH_LINES};
+
+/// A record of a BED file, borrowing its fields from the input line
+#[derive(Debug, Clone)]
+pub(crate) struct BedRecord<'l> {
+    pub(crate) fields: Vec<&'l str>,
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    /// The record as a BED12 entry, if it has block fields
+    pub(crate) bed12: Option<BedEntry>,
+}
+
+impl<'l> BedRecord<'l> {
+    /// Parse a BED line, tab-separated or, failing that, whitespace-separated
+    ///
+    /// # Arguments
+    /// * `line` - A BED line without its line terminator
+    /// * `number` - The 1-based line number, for error messages
+    ///
+    /// # Returns
+    /// * Result<BedRecord>
+    pub(crate) fn parse(line: &'l str, number: usize) -> Result<Self> {
+        let fields: Vec<&str> = match line.contains('\t') {
+            true => line.split('\t').collect(),
+            false => line.split_whitespace().collect(),
+        };
+        if fields.len() < 3 {
+            anyhow::bail!("Malformed BED record at line {}: expected at least 3 fields", number);
+        }
+        let coord = |i: usize, name: &str| -> Result<u64> {
+            fields[i]
+                .parse::<u64>()
+                .with_context(|| format!("Malformed BED record at line {}: invalid {} {:?}", number, name, fields[i]))
+        };
+        let (start, end) = (coord(1, "start")?, coord(2, "end")?);
+        if end < start {
+            anyhow::bail!("Malformed BED record at line {}: end {} before start {}", number, end, start);
+        }
+        let mut record = Self { fields, start, end, bed12: None };
+        if record.fields.len() >= 12 {
+            record.bed12 = Some(record.to_bed12(number)?);
+        }
+        Ok(record)
+    }
+
+    // Private builder of the BED12 entry of a record with block fields
+    fn to_bed12(&self, number: usize) -> Result<BedEntry> {
+        let fields = &self.fields;
+        let int = |i: usize, name: &str| -> Result<u64> {
+            fields[i]
+                .parse::<u64>()
+                .with_context(|| format!("Malformed BED12 record at line {}: invalid {} {:?}", number, name, fields[i]))
+        };
+        let list = |i: usize, name: &str| -> Result<Vec<u64>> {
+            fields[i]
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(|x| x.parse::<u64>())
+                .collect::<Result<Vec<u64>, _>>()
+                .with_context(|| format!("Malformed BED12 record at line {}: invalid {} {:?}", number, name, fields[i]))
+        };
+        let (count, sizes, starts) = (int(9, "blockCount")?, list(10, "blockSizes")?, list(11, "blockStarts")?);
+        if sizes.len() as u64 != count || starts.len() as u64 != count {
+            anyhow::bail!(
+                "Malformed BED12 record at line {}: {} blocks with {} sizes and {} starts",
+                number,
+                count,
+                sizes.len(),
+                starts.len()
+            );
+        }
+        Ok(BedEntry::bed12(
+            fields[0].to_string(),
+            self.start,
+            self.end,
+            fields[3].to_string(),
+            fields[4].to_string(),
+            fields[5] != "-",
+            int(6, "thickStart")?,
+            int(7, "thickEnd")?,
+            fields[8].to_string(),
+            count as u16,
+            sizes,
+            starts,
+        ))
+    }
+
+    /// Get the reference chromosome of the record
+    ///
+    /// # Returns
+    /// * &str
+    pub(crate) fn chr(&self) -> &'l str {
+        self.fields[0]
+    }
+
+    /// Write the record with lifted coordinates, flipping its strand column if the
+    /// record changes orientation
+    ///
+    /// # Arguments
+    /// * `lift` - The lifted coordinates
+    /// * `out` - A writer
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub(crate) fn write_lifted<W: Write>(&self, lift: &Lift<'_>, out: &mut W) -> Result<()> {
+        write!(out, "{}\t{}\t{}", lift.chr, lift.start, lift.end)?;
+        for (i, field) in self.fields.iter().enumerate().skip(3) {
+            let field = match (i, *field, lift.strand) {
+                (5, "+", '-') => "-",
+                (5, "-", '-') => "+",
+                _ => field,
+            };
+            write!(out, "\t{}", field)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+    /// Write the record as a projected BED12 entry, keeping its extra columns and
+    /// the strand column of unstranded records
+    ///
+    /// # Arguments
+    /// * `entry` - The projected BED12 entry
+    /// * `out` - A writer
+    ///
+    /// # Returns
+    /// * Result<()>
+    pub(crate) fn write_projected<W: Write>(&self, entry: &BedEntry, out: &mut W) -> Result<()> {
+        let line = to_line(entry, 12)?;
+        match self.fields[5] {
+            "+" | "-" => write!(out, "{}", line)?,
+            // BED entries are either on the positive or the negative strand
+            strand => {
+                let fields: Vec<&str> = line
+                    .split('\t')
+                    .enumerate()
+                    .map(|(i, field)| if i == 5 { strand } else { field })
+                    .collect();
+                write!(out, "{}", fields.join("\t"))?;
+            }
+        }
+        for field in &self.fields[12..] {
+            write!(out, "\t{}", field)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+// Lift of a BED record with its projected BED12 entry, if any, or the reason it could not
+// be lifted with the indices of the blocks that could not be mapped
+type Projected<'a> = std::result::Result<(Lift<'a>, Option<BedEntry>), (UnmappedReason, Vec<usize>)>;
+
+// Private check for BED lines that carry no record
+fn is_header(line: &str) -> bool {
+    line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser")
+}
+
+impl<'a> Liftover<'a> {
+    /// Lift a BED file, as UCSC `liftOver`
+    ///
+    /// # Arguments
+    /// * `input` - Path to a plain or compressed BED file
+    /// * `lifted` - Path to the BED file of lifted records
+    /// * `unmapped` - Path to the BED file of records that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover};
+    ///
+    /// let chains = Reader::from_file("/path/to/hg38ToMm10.over.chain.gz").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new().with_min_match(0.1)).unwrap();
+    /// let report = liftover.lift_bed_file("/path/to/peaks.bed", "/path/to/lifted.bed", "/path/to/unmapped.bed").unwrap();
+    /// println!("{} lifted, {} unmapped", report.lifted, report.unmapped.len());
+    /// ```
+    pub fn lift_bed_file<P, L, U>(&self, input: P, lifted: L, unmapped: U) -> Result<LiftReport>
+    where
+        P: AsRef<Path> + Debug,
+        L: AsRef<Path> + Debug,
+        U: AsRef<Path> + Debug,
+    {
+        let file = File::open(&input).with_context(|| format!("Failed to open file {:?}", input))?;
+        let lifted = File::create(&lifted).with_context(|| format!("Failed to create file {:?}", lifted))?;
+        let unmapped = File::create(&unmapped).with_context(|| format!("Failed to create file {:?}", unmapped))?;
+        self.lift_bed(file, BufWriter::new(lifted), BufWriter::new(unmapped))
+    }
+
+    /// Lift BED records from any reader
+    ///
+    /// Records are read in batches of [`crate::liftover::BATCH_LINES`] lines, lifted in parallel
+    /// over reference chromosomes and written in input order; `#`, `track` and `browser` lines
+    /// are copied to the lifted output. The strand
+    /// column of records lifted through a chain on the negative query strand is flipped.
+    /// The blocks and thick coordinates of BED12 records are projected through the chain
+    /// the record was lifted through (see [`crate::cmap::chain::Chain::project_bed12`]);
+    /// records with blocks that cannot be projected, or that keep fewer than
+    /// [`crate::liftover::LiftOptions::min_match`] of their bases aligned, are left unmapped
+    /// as partially deleted, listing the 0-based indices of those blocks in
+    /// [`UnmappedRecord::blocks`] and in their `#` line.
+    /// Records that cannot be lifted are written unchanged, each preceded by a `#` line
+    /// giving the reason, as in UCSC `liftOver` unmapped files.
+    ///
+    /// # Arguments
+    /// * `input` - A reader of plain or compressed BED data
+    /// * `lifted` - A writer for lifted records
+    /// * `unmapped` - A writer for annotated records that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, UnmappedReason};
+    ///
+    /// let chains = Reader::from_bytes(b"chain 100 chr1 1000 + 100 300 chrA 5000 - 1000 1210 1\n50\t10\t20\n140\n\n").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    ///
+    /// let bed = b"chr1\t110\t130\tpeak1\t0\t+\nchr1\t150\t160\tpeak2\t0\t+\n";
+    /// let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+    /// let report = liftover.lift_bed(&bed[..], &mut lifted, &mut unmapped).unwrap();
+    ///
+    /// assert_eq!(report.lifted, 1);
+    /// assert_eq!(report.unmapped[0].reason, UnmappedReason::InGap);
+    /// assert_eq!(lifted, b"chrA\t3970\t3990\tpeak1\t0\t-\n");
+    /// assert_eq!(unmapped, b"#Enclosed in a chain gap\nchr1\t150\t160\tpeak2\t0\t+\n");
+    /// ```
+    pub fn lift_bed<R, L, U>(&self, input: R, mut lifted: L, mut unmapped: U) -> Result<LiftReport>
+    where
+        R: Read,
+        L: Write,
+        U: Write,
+    {
+        let mut input = LineBatches::new(input, "BED")?;
+        let mut report = LiftReport::default();
+        loop {
+            let batch = input.read_batch(BATCH_LINES, |_| None)?;
+            if batch.is_empty() {
+                break;
+            }
+            let mut lines: Vec<(usize, usize, &str)> = Vec::new();
+            let mut records: Vec<BedRecord<'_>> = Vec::new();
+            for (number, line) in &batch {
+                if is_header(line) {
+                    lines.push((usize::MAX, *number, line));
+                } else {
+                    lines.push((records.len(), *number, line));
+                    records.push(BedRecord::parse(line, *number)?);
+                }
+            }
+
+            let lifts = self.lift_records(&records, self.options.ignore_undefined, |record| {
+                (record.chr(), record.start, record.end)
+            });
+            for (index, number, line) in lines {
+                let Some(lift) = lifts.get(index) else {
+                    writeln!(lifted, "{}", line)?;
+                    continue;
+                };
+                let record = &records[index];
+                let lift = match lift {
+                    Ok(lift) => self.project_blocks(record, lift)?,
+                    Err(reason) => Err((*reason, Vec::new())),
+                };
+                match lift {
+                    Ok((lift, None)) => record.write_lifted(&lift, &mut lifted)?,
+                    Ok((_, Some(entry))) => record.write_projected(&entry, &mut lifted)?,
+                    Err((reason, blocks)) => {
+                        let record = UnmappedRecord {
+                            line: number as u64,
+                            record: line.to_string(),
+                            reason,
+                            blocks,
+                        };
+                        writeln!(unmapped, "#{}\n{}", record, line)?;
+                        report.unmapped.push(record);
+                        continue;
+                    }
+                }
+                report.lifted += 1;
+            }
+        }
+        lifted.flush()?;
+        unmapped.flush()?;
+        Ok(report)
+    }
+
+    // Private projection of the blocks of a lifted BED12 record through the chain it was lifted
+    // through; fails with the indices of the blocks that could not be mapped
+    fn project_blocks(
+        &self,
+        record: &BedRecord<'_>,
+        lift: &Lift<'a>,
+    ) -> Result<Projected<'a>> {
+        let (Some(entry), Some(chain)) = (record.bed12.as_ref(), self.chain(&lift.chain_id)) else {
+            return Ok(Ok((*lift, None)));
+        };
+        let projection = chain.project_bed12(entry, self.options.min_match)?;
+        Ok(match projection.entry {
+            Some(entry) if projection.unmapped.is_empty() => Ok((*lift, Some(entry))),
+            _ => Err((UnmappedReason::PartiallyDeleted, projection.unmapped)),
+        })
+    }
+}
@@ -0,0 +1,461 @@
+use anyhow::{Context, Result};
+use fxhash::FxHashMap;
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::liftover::{Lift, LineBatches, LiftReport, Liftover, SplitPolicy, UnmappedReason, UnmappedRecord};
+
+/// Attribute set on features lifted under [`SplitPolicy::Flag`] whose parts landed on
+/// different chains or chromosomes
+pub const SPLIT_ATTRIBUTE: &str = "lift_split";
+
+/// Attribute syntax of an annotation file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GffFormat {
+    /// GFF3, with `key=value` attributes and `ID`/`Parent` relationships
+    Gff3,
+    /// GTF, with `key "value";` attributes and `gene_id`/`transcript_id` relationships
+    Gtf,
+}
+
+impl GffFormat {
+    /// Guess the format of an attribute column
+    ///
+    /// # Arguments
+    /// * `attributes` - The 9th column of a record
+    ///
+    /// # Returns
+    /// * GffFormat
+    pub fn detect(attributes: &str) -> Self {
+        let key = attributes.trim_start().split([';', ' ']).next().unwrap_or_default();
+        match key.contains('=') {
+            true => GffFormat::Gff3,
+            false => GffFormat::Gtf,
+        }
+    }
+}
+
+/// A record of a GFF3 or GTF file, borrowing its fields from the input line
+#[derive(Debug, Clone)]
+struct GffRecord<'l> {
+    fields: Vec<&'l str>,
+    start: u64,
+    end: u64,
+}
+
+impl<'l> GffRecord<'l> {
+    // Private parser of a tab-separated record; the interval is made 0-based, end-exclusive
+    fn parse(line: &'l str, number: usize) -> Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 9 {
+            anyhow::bail!("Malformed GFF record at line {}: expected 9 fields", number);
+        }
+        let coord = |i: usize, name: &str| -> Result<u64> {
+            fields[i]
+                .parse::<u64>()
+                .ok()
+                .filter(|x| *x > 0)
+                .with_context(|| format!("Malformed GFF record at line {}: invalid {} {:?}", number, name, fields[i]))
+        };
+        let (start, end) = (coord(3, "start")? - 1, coord(4, "end")?);
+        if end < start {
+            anyhow::bail!("Malformed GFF record at line {}: end {} before start {}", number, end, start + 1);
+        }
+        Ok(Self { fields, start, end })
+    }
+
+    // Private getter of an attribute value
+    fn attribute(&self, key: &str, format: GffFormat) -> Option<&'l str> {
+        self.fields[8].split(';').map(str::trim).find_map(|pair| match format {
+            GffFormat::Gff3 => pair.strip_prefix(key)?.strip_prefix('='),
+            GffFormat::Gtf => Some(pair.strip_prefix(key)?.strip_prefix(' ')?.trim().trim_matches('"')),
+        })
+    }
+
+    // Private writer of a record at its lifted position
+    fn write_lifted<W: Write>(&self, lift: &Lift<'_>, flagged: bool, format: GffFormat, out: &mut W) -> Result<()> {
+        let strand = match (self.fields[6], lift.strand) {
+            ("+", '-') => "-",
+            ("-", '-') => "+",
+            (strand, _) => strand,
+        };
+        // the `.` placeholder of an empty attribute column is replaced by the flag
+        let attributes = match (flagged, self.fields[8].trim()) {
+            (true, ".") => "",
+            _ => self.fields[8],
+        };
+        write!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            lift.chr,
+            self.fields[1],
+            self.fields[2],
+            lift.start + 1,
+            lift.end,
+            self.fields[5],
+            strand,
+            self.fields[7],
+            attributes
+        )?;
+        if flagged {
+            let attributes = attributes.trim_end();
+            let sep = match (attributes.is_empty(), attributes.ends_with(';'), format) {
+                (true, _, _) | (false, true, GffFormat::Gff3) => "",
+                (false, true, GffFormat::Gtf) => " ",
+                (false, false, GffFormat::Gff3) => ";",
+                (false, false, GffFormat::Gtf) => "; ",
+            };
+            match format {
+                GffFormat::Gff3 => write!(out, "{}{}=true", sep, SPLIT_ATTRIBUTE)?,
+                GffFormat::Gtf => write!(out, "{}{} \"true\";", sep, SPLIT_ATTRIBUTE)?,
+            }
+        }
+        for field in &self.fields[9..] {
+            write!(out, "\t{}", field)?;
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+}
+
+/// Parent/child relationships between the records of an annotation file
+///
+/// Every record is a node; GTF transcripts and genes only given through the
+/// `transcript_id`/`gene_id` of their parts get a node without a record.
+#[derive(Debug, Default)]
+struct Hierarchy {
+    children: Vec<Vec<usize>>,
+}
+
+impl Hierarchy {
+    // Private builder of the hierarchy of parsed records
+    fn new(records: &[GffRecord<'_>], format: GffFormat) -> Self {
+        let mut tree = Self::default();
+        let mut ids: FxHashMap<(&str, &str), usize> = FxHashMap::default();
+        for record in records {
+            let node = tree.push();
+            let key = match format {
+                GffFormat::Gff3 => record.attribute("ID", format).map(|id| ("", id)),
+                GffFormat::Gtf => match record.fields[2] {
+                    "gene" => record.attribute("gene_id", format).map(|id| ("gene", id)),
+                    "transcript" | "mRNA" => record.attribute("transcript_id", format).map(|id| ("transcript", id)),
+                    _ => None,
+                },
+            };
+            if let Some(key) = key {
+                ids.entry(key).or_insert(node);
+            }
+        }
+
+        for (node, record) in records.iter().enumerate() {
+            match format {
+                GffFormat::Gff3 => {
+                    for parent in record.attribute("Parent", format).into_iter().flat_map(|x| x.split(',')) {
+                        if let Some(parent) = ids.get(&("", parent)) {
+                            tree.children[*parent].push(node);
+                        }
+                    }
+                }
+                GffFormat::Gtf => {
+                    let gene = record.attribute("gene_id", format);
+                    let transcript = record.attribute("transcript_id", format);
+                    let parent = match record.fields[2] {
+                        "gene" => None,
+                        "transcript" | "mRNA" => gene.map(|gene| tree.node(&mut ids, ("gene", gene), None)),
+                        _ => {
+                            let gene = gene.map(|gene| tree.node(&mut ids, ("gene", gene), None));
+                            match transcript {
+                                Some(transcript) => Some(tree.node(&mut ids, ("transcript", transcript), gene)),
+                                None => gene,
+                            }
+                        }
+                    };
+                    if let Some(parent) = parent.filter(|parent| *parent != node) {
+                        tree.children[parent].push(node);
+                    }
+                }
+            }
+        }
+        tree
+    }
+
+    // Private constructor of a node; nodes of records come first, in record order
+    fn push(&mut self) -> usize {
+        self.children.push(Vec::new());
+        self.children.len() - 1
+    }
+
+    // Private getter of the node of a GTF gene or transcript, added without a record if missing
+    fn node<'l>(
+        &mut self,
+        ids: &mut FxHashMap<(&'l str, &'l str), usize>,
+        key: (&'l str, &'l str),
+        parent: Option<usize>,
+    ) -> usize {
+        if let Some(node) = ids.get(&key) {
+            return *node;
+        }
+        let node = self.push();
+        ids.insert(key, node);
+        if let Some(parent) = parent {
+            self.children[parent].push(node);
+        }
+        node
+    }
+}
+
+// Private bottom-up derivation of the lifts of the nodes of a hierarchy
+struct Resolver<'t, 'a> {
+    tree: &'t Hierarchy,
+    own: Vec<Result<Lift<'a>, UnmappedReason>>,
+    lifts: Vec<Option<Result<Lift<'a>, UnmappedReason>>>,
+    flagged: Vec<bool>,
+    policy: SplitPolicy,
+}
+
+impl<'a> Resolver<'_, 'a> {
+    // Private lift of a node: its own for leaves, the span of its lifted children otherwise;
+    // split nodes kept under SplitPolicy::Flag span their children on the chain holding
+    // most of their lifted bases, and flag their ancestors
+    fn resolve(&mut self, node: usize) -> Result<Lift<'a>, UnmappedReason> {
+        if let Some(lift) = self.lifts[node] {
+            return lift;
+        }
+        // a cyclic hierarchy falls back to the own lift of the node
+        self.lifts[node] = Some(self.own[node]);
+        let children = &self.tree.children[node];
+        if children.is_empty() {
+            return self.own[node];
+        }
+
+        // span and lifted bases of the children on every chain
+        let mut spans: Vec<(Lift<'a>, u64)> = Vec::new();
+        let mut failure = None;
+        let mut flagged = false;
+        for child in children.clone() {
+            match self.resolve(child) {
+                Ok(lift) => {
+                    flagged |= self.flagged[child];
+                    let bases = lift.end - lift.start;
+                    match spans.iter_mut().find(|(span, _)| span.chr == lift.chr && span.chain_id == lift.chain_id) {
+                        Some((span, total)) => {
+                            span.start = span.start.min(lift.start);
+                            span.end = span.end.max(lift.end);
+                            *total += bases;
+                        }
+                        None => spans.push((lift, bases)),
+                    }
+                }
+                Err(reason) => failure = failure.or(Some(reason)),
+            }
+        }
+        let lift = match (spans.as_slice(), self.policy) {
+            ([], _) => Err(failure.unwrap_or(UnmappedReason::Deleted)),
+            ([(span, _)], _) => {
+                self.flagged[node] |= flagged;
+                Ok(*span)
+            }
+            (_, SplitPolicy::Drop) => {
+                self.mark(node, |this, node| this.lifts[node] = Some(Err(UnmappedReason::Split)));
+                Err(UnmappedReason::Split)
+            }
+            ([first, rest @ ..], SplitPolicy::Flag) => {
+                let (span, _) = rest.iter().fold(*first, |best, x| if x.1 > best.1 { *x } else { best });
+                self.mark(node, |this, node| this.flagged[node] = true);
+                Ok(span)
+            }
+        };
+        self.lifts[node] = Some(lift);
+        lift
+    }
+
+    // Private visitor of a node and its descendants
+    fn mark<F: Fn(&mut Self, usize)>(&mut self, node: usize, f: F) {
+        let mut stack = vec![node];
+        let mut seen = vec![false; self.lifts.len()];
+        while let Some(node) = stack.pop() {
+            if !std::mem::replace(&mut seen[node], true) {
+                f(self, node);
+                stack.extend(self.tree.children[node].iter().copied());
+            }
+        }
+    }
+}
+
+impl<'a> Liftover<'a> {
+    /// Lift a GFF3 or GTF annotation file
+    ///
+    /// # Arguments
+    /// * `input` - Path to a plain or compressed GFF3 or GTF file
+    /// * `lifted` - Path to the annotation file of lifted features
+    /// * `unmapped` - Path to the annotation file of features that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, SplitPolicy};
+    ///
+    /// let chains = Reader::from_file("/path/to/hg38ToMm10.over.chain.gz").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new().with_split(SplitPolicy::Flag)).unwrap();
+    /// let report = liftover.lift_gff_file("/path/to/genes.gff3", "/path/to/lifted.gff3", "/path/to/unmapped.gff3").unwrap();
+    /// println!("{}", report);
+    /// ```
+    pub fn lift_gff_file<P, L, U>(&self, input: P, lifted: L, unmapped: U) -> Result<LiftReport>
+    where
+        P: AsRef<Path> + Debug,
+        L: AsRef<Path> + Debug,
+        U: AsRef<Path> + Debug,
+    {
+        let file = File::open(&input).with_context(|| format!("Failed to open file {:?}", input))?;
+        let lifted = File::create(&lifted).with_context(|| format!("Failed to create file {:?}", lifted))?;
+        let unmapped = File::create(&unmapped).with_context(|| format!("Failed to create file {:?}", unmapped))?;
+        self.lift_gff(file, BufWriter::new(lifted), BufWriter::new(unmapped))
+    }
+
+    /// Lift GFF3 or GTF features from any reader, keeping their hierarchy
+    ///
+    /// The format is guessed from the first feature with attributes (see [`GffFormat::detect`]).
+    /// Every feature is lifted on its own; features with children (genes, transcripts) then
+    /// span their lifted children. Children landing on different chains or chromosomes split
+    /// their parent, which is handled according to [`crate::liftover::LiftOptions::split`]:
+    /// under [`SplitPolicy::Drop`] the parent and all its descendants are unmapped, under
+    /// [`SplitPolicy::Flag`] the parent spans its children on the chain holding most of their
+    /// lifted bases, and it, its ancestors and its descendants get a [`SPLIT_ATTRIBUTE`] attribute.
+    /// The strand of features lifted through a chain on the negative query strand is flipped.
+    ///
+    /// `##sequence-region` lines of the lifted output are rewritten from the query chromosomes
+    /// of the chains and a `##FASTA` section ends the input. Features are written in input
+    /// order; unmapped ones are preceded by a `#` line giving the reason.
+    ///
+    /// Features are read and lifted one run of lines of the same chromosome at a time, so
+    /// that only the largest chromosome is held in memory; parents are found among the
+    /// features of their run, which holds all of them in files grouped by chromosome.
+    ///
+    /// # Arguments
+    /// * `input` - A reader of plain or compressed GFF3 or GTF data
+    /// * `lifted` - A writer for lifted features
+    /// * `unmapped` - A writer for annotated features that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, UnmappedReason};
+    ///
+    /// let chains = Reader::from_bytes(b"chain 100 chr1 1000 + 100 300 chrA 5000 - 1000 1210 1\n50\t10\t20\n140\n\n").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    ///
+    /// let gff = b"##gff-version 3\n\
+    /// chr1\tsrc\tgene\t101\t300\t.\t+\t.\tID=g1\n\
+    /// chr1\tsrc\tmRNA\t101\t300\t.\t+\t.\tID=t1;Parent=g1\n\
+    /// chr1\tsrc\texon\t111\t140\t.\t+\t.\tParent=t1\n\
+    /// chr1\tsrc\texon\t201\t250\t.\t+\t.\tParent=t1\n\
+    /// chr1\tsrc\texon\t151\t160\t.\t+\t.\tParent=t1\n";
+    /// let (mut lifted, mut unmapped) = (Vec::new(), Vec::new());
+    /// let report = liftover.lift_gff(&gff[..], &mut lifted, &mut unmapped).unwrap();
+    ///
+    /// assert_eq!(report.lifted, 4);
+    /// assert_eq!(report.unmapped[0].reason, UnmappedReason::InGap);
+    /// let lifted = String::from_utf8(lifted).unwrap();
+    /// assert!(lifted.contains("chrA\tsrc\tgene\t3841\t3990\t.\t-\t.\tID=g1\n"));
+    /// ```
+    pub fn lift_gff<R, L, U>(&self, input: R, mut lifted: L, mut unmapped: U) -> Result<LiftReport>
+    where
+        R: Read,
+        L: Write,
+        U: Write,
+    {
+        let mut input = LineBatches::new(input, "GFF")?;
+        let mut detected: Option<GffFormat> = None;
+        let mut report = LiftReport::default();
+        let mut regions = false;
+        let mut fasta = false;
+        while !fasta {
+            // features only relate to features of their chromosome
+            let batch = input.read_batch(usize::MAX, |line| match line.starts_with('#') || line.trim().is_empty() {
+                true => None,
+                false => line.split('\t').next(),
+            })?;
+            if batch.is_empty() {
+                break;
+            }
+            let mut lines: Vec<(usize, usize, &str)> = Vec::new();
+            let mut records: Vec<GffRecord<'_>> = Vec::new();
+            for (number, line) in &batch {
+                if line.starts_with("##FASTA") {
+                    fasta = true;
+                    break;
+                } else if line.starts_with('#') || line.trim().is_empty() {
+                    lines.push((usize::MAX, *number, line));
+                } else {
+                    lines.push((records.len(), *number, line));
+                    records.push(GffRecord::parse(line, *number)?);
+                }
+            }
+            detected = detected.or_else(|| {
+                records
+                    .iter()
+                    .find(|record| record.fields[8].trim() != ".")
+                    .map(|record| GffFormat::detect(record.fields[8]))
+            });
+            let format = detected.unwrap_or(GffFormat::Gff3);
+
+            let tree = Hierarchy::new(&records, format);
+            let mut own = self.lift_records(&records, self.options.ignore_undefined, |record| {
+                (record.fields[0], record.start, record.end)
+            });
+            own.resize(tree.children.len(), Err(UnmappedReason::Deleted));
+            let mut resolver = Resolver {
+                tree: &tree,
+                own,
+                lifts: vec![None; tree.children.len()],
+                flagged: vec![false; tree.children.len()],
+                policy: self.options.split,
+            };
+            // derive every node before writing, so that dropped parents reach all their descendants
+            for node in 0..tree.children.len() {
+                let _ = resolver.resolve(node);
+            }
+
+            for (index, number, line) in lines {
+                let Some(record) = records.get(index) else {
+                    if !line.starts_with("##sequence-region") {
+                        writeln!(lifted, "{}", line)?;
+                    } else if !std::mem::replace(&mut regions, true) {
+                        for (chr, size) in self.query_sizes() {
+                            writeln!(lifted, "##sequence-region {} 1 {}", chr, size)?;
+                        }
+                    }
+                    continue;
+                };
+                match resolver.resolve(index) {
+                    Ok(lift) => {
+                        record.write_lifted(&lift, resolver.flagged[index], format, &mut lifted)?;
+                        report.lifted += 1;
+                    }
+                    Err(reason) => {
+                        writeln!(unmapped, "#{}\n{}", reason, line)?;
+                        report.unmapped.push(UnmappedRecord {
+                            line: number as u64,
+                            record: line.to_string(),
+                            reason,
+                            blocks: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        lifted.flush()?;
+        unmapped.flush()?;
+        Ok(report)
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use std::{
+    cmp::{max, min},
+    fmt::Debug,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::liftover::{Lift, LineBatches, LiftReport, Liftover, UnmappedReason, UnmappedRecord, BATCH_LINES};
+
+/// INFO flag set on records lifted through a chain on the negative query strand,
+/// whose REF and ALT alleles must be reverse-complemented
+pub const REVCOMP_FLAG: &str = "REVCOMP";
+
+/// A record of a VCF file, borrowing its fields from the input line
+#[derive(Debug, Clone)]
+struct VcfRecord<'l> {
+    fields: Vec<&'l str>,
+    start: u64,
+    end: u64,
+    /// 1-based, inclusive `INFO/END` of the record, if any
+    info_end: Option<u64>,
+}
+
+impl<'l> VcfRecord<'l> {
+    // Private parser of a tab-separated VCF record; the interval spans the REF allele
+    fn parse(line: &'l str, number: usize) -> Result<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 8 {
+            anyhow::bail!("Malformed VCF record at line {}: expected at least 8 fields", number);
+        }
+        let pos = fields[1]
+            .parse::<u64>()
+            .ok()
+            .filter(|pos| *pos > 0)
+            .with_context(|| format!("Malformed VCF record at line {}: invalid POS {:?}", number, fields[1]))?;
+        let (start, end) = (pos - 1, pos - 1 + fields[3].len() as u64);
+        let info_end = match fields[7].split(';').find_map(|x| x.strip_prefix("END=")) {
+            Some(value) => Some(value.parse::<u64>().ok().filter(|end| *end >= pos).with_context(|| {
+                format!("Malformed VCF record at line {}: invalid INFO/END {:?}", number, value)
+            })?),
+            None => None,
+        };
+        Ok(Self { fields, start, end, info_end })
+    }
+
+    // Private writer of a record at its lifted position; `end` is the lift of the INFO/END base
+    fn write_lifted<W: Write>(&self, lift: &Lift<'_>, end: Option<&Lift<'_>>, out: &mut W) -> Result<()> {
+        // the record spans from POS to END, which swap sides on the negative query strand
+        let (start, stop) = end.map_or((lift.start, lift.end), |x| (min(lift.start, x.start), max(lift.end, x.end)));
+        write!(out, "{}\t{}", lift.chr, start + 1)?;
+        for (i, field) in self.fields.iter().enumerate().skip(2) {
+            if i != 7 {
+                write!(out, "\t{}", field)?;
+                continue;
+            }
+            let mut info: Vec<String> = field
+                .split(';')
+                .filter(|x| *x != ".")
+                .map(|x| match x.starts_with("END=") {
+                    true => format!("END={}", stop),
+                    false => x.to_string(),
+                })
+                .collect();
+            if lift.strand == '-' {
+                info.push(REVCOMP_FLAG.to_string());
+            }
+            match info.is_empty() {
+                true => write!(out, "\t.")?,
+                false => write!(out, "\t{}", info.join(";"))?,
+            }
+        }
+        writeln!(out)?;
+        Ok(())
+    }
+
+}
+
+// Private writer of a rejected record, with the reason as its FILTER; the missing
+// columns of a malformed record are filled with `.`
+fn write_rejected<W: Write>(line: &str, reason: UnmappedReason, out: &mut W) -> Result<()> {
+    let mut fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 8 {
+        fields.resize(8, ".");
+    }
+    fields[6] = reason.id();
+    writeln!(out, "{}", fields.join("\t"))?;
+    Ok(())
+}
+
+impl<'a> Liftover<'a> {
+    /// Lift a VCF file
+    ///
+    /// # Arguments
+    /// * `input` - Path to a plain or gzipped VCF file
+    /// * `lifted` - Path to the VCF file of lifted records
+    /// * `rejected` - Path to the VCF file of records that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```no_run
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover};
+    ///
+    /// let chains = Reader::from_file("/path/to/hg19ToHg38.over.chain.gz").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    /// let report = liftover.lift_vcf_file("/path/to/calls.vcf.gz", "/path/to/lifted.vcf", "/path/to/rejected.vcf").unwrap();
+    /// println!("{}", report);
+    /// ```
+    pub fn lift_vcf_file<P, L, U>(&self, input: P, lifted: L, rejected: U) -> Result<LiftReport>
+    where
+        P: AsRef<Path> + Debug,
+        L: AsRef<Path> + Debug,
+        U: AsRef<Path> + Debug,
+    {
+        let file = File::open(&input).with_context(|| format!("Failed to open file {:?}", input))?;
+        let lifted = File::create(&lifted).with_context(|| format!("Failed to create file {:?}", lifted))?;
+        let rejected = File::create(&rejected).with_context(|| format!("Failed to create file {:?}", rejected))?;
+        self.lift_vcf(file, BufWriter::new(lifted), BufWriter::new(rejected))
+    }
+
+    /// Lift VCF records from any reader
+    ///
+    /// The REF allele of every record is projected through aligned blocks only: records
+    /// enclosed in a chain gap or whose REF allele spans one are rejected, whatever
+    /// [`crate::liftover::LiftOptions::ignore_undefined`]. `##contig` lines of the lifted
+    /// output are rewritten from the query chromosomes and sizes of the chains. Records
+    /// lifted through a chain on the negative query strand are placed at the leftmost base
+    /// of their REF allele and flagged with [`REVCOMP_FLAG`]; their alleles are left as they
+    /// are. The last base of records carrying `INFO/END` is lifted through the chain their
+    /// REF allele was lifted through and END is rewritten: such records are placed at the
+    /// leftmost base of their lifted POS-to-END span, and rejected if that base falls in a
+    /// gap of the chain or outside it. The rejected output keeps the input header and sets
+    /// the `FILTER` of every record to the [`UnmappedReason::id`] of its reason; records
+    /// that cannot be parsed, including `#` lines following the first record, are rejected as
+    /// [`UnmappedReason::Malformed`], their missing columns filled with `.`.
+    /// Records are read and lifted in batches of [`crate::liftover::BATCH_LINES`] lines.
+    ///
+    /// # Arguments
+    /// * `input` - A reader of plain or gzipped VCF data
+    /// * `lifted` - A writer for lifted records
+    /// * `rejected` - A writer for records that could not be lifted
+    ///
+    /// # Returns
+    /// * Result<LiftReport>
+    ///
+    /// # Example
+    /// ```
+    /// use chaintools::io::reader::Reader;
+    /// use chaintools::liftover::{LiftOptions, Liftover, UnmappedReason};
+    ///
+    /// let chains = Reader::from_bytes(b"chain 100 chr1 1000 + 100 300 chrA 5000 - 1000 1210 1\n50\t10\t20\n140\n\n").unwrap();
+    /// let liftover = Liftover::new(&chains, LiftOptions::new()).unwrap();
+    ///
+    /// let vcf = b"##fileformat=VCFv4.2\n##contig=<ID=chr1,length=1000>\n\
+    /// #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n\
+    /// chr1\t111\trs1\tA\tG\t50\tPASS\tDP=10\n\
+    /// chr1\t121\tsv1\tN\t<DEL>\t50\tPASS\tSVTYPE=DEL;END=200\n\
+    /// chr1\t141\tsv2\tN\t<DEL>\t50\tPASS\tSVTYPE=DEL;END=155\n\
+    /// chr1\t155\trs2\tC\tT\t50\tPASS\t.\n\
+    /// chr1\t0\trs3\tC\tT\n";
+    /// let (mut lifted, mut rejected) = (Vec::new(), Vec::new());
+    /// let report = liftover.lift_vcf(&vcf[..], &mut lifted, &mut rejected).unwrap();
+    ///
+    /// assert_eq!(report.lifted, 2);
+    /// assert_eq!(report.unmapped[0].reason, UnmappedReason::InGap);
+    /// assert_eq!(report.unmapped[1].reason, UnmappedReason::InGap);
+    /// assert_eq!((report.unmapped[2].reason, report.unmapped[2].line), (UnmappedReason::Malformed, 8));
+    /// let lifted = String::from_utf8(lifted).unwrap();
+    /// assert!(lifted.contains("##contig=<ID=chrA,length=5000>\n"));
+    /// assert!(lifted.contains("chrA\t3990\trs1\tA\tG\t50\tPASS\tDP=10;REVCOMP\n"));
+    /// assert!(lifted.ends_with("chrA\t3891\tsv1\tN\t<DEL>\t50\tPASS\tSVTYPE=DEL;END=3980;REVCOMP\n"));
+    /// let rejected = String::from_utf8(rejected).unwrap();
+    /// assert!(rejected.contains("chr1\t141\tsv2\tN\t<DEL>\t50\tInGap\tSVTYPE=DEL;END=155\n"));
+    /// assert!(rejected.contains("chr1\t155\trs2\tC\tT\t50\tInGap\t.\n"));
+    /// assert!(rejected.ends_with("chr1\t0\trs3\tC\tT\t.\tMalformed\t.\n"));
+    /// ```
+    pub fn lift_vcf<R, L, U>(&self, input: R, mut lifted: L, mut rejected: U) -> Result<LiftReport>
+    where
+        R: Read,
+        L: Write,
+        U: Write,
+    {
+        let mut input = LineBatches::new(input, "VCF")?;
+        // header lines, until the first record
+        let mut header: Option<Vec<String>> = Some(Vec::new());
+        let mut report = LiftReport::default();
+        loop {
+            let batch = input.read_batch(BATCH_LINES, |_| None)?;
+            if batch.is_empty() {
+                break;
+            }
+            // lines of records, with the index of the record unless it is malformed
+            let mut lines: Vec<(usize, &str, Option<usize>)> = Vec::new();
+            let mut records: Vec<VcfRecord<'_>> = Vec::new();
+            for (number, line) in &batch {
+                if let Some(header) = header.as_mut().filter(|_| line.starts_with('#')) {
+                    header.push(line.clone());
+                    continue;
+                } else if line.trim().is_empty() {
+                    continue;
+                }
+                if let Some(header) = header.take() {
+                    self.write_vcf_header(&header, &mut lifted, &mut rejected)?;
+                }
+                match VcfRecord::parse(line, *number) {
+                    Ok(record) => {
+                        lines.push((*number, line, Some(records.len())));
+                        records.push(record);
+                    }
+                    Err(_) => lines.push((*number, line, None)),
+                }
+            }
+
+            let lifts = self.lift_records(&records, true, |record| (record.fields[0], record.start, record.end));
+            for (number, line, index) in lines {
+                let lift = match index {
+                    Some(index) => {
+                        let record = &records[index];
+                        // a gap inside the REF allele would shift the alleles against the query
+                        let lift = lifts[index].and_then(|lift| {
+                            match lift.end - lift.start == record.end - record.start {
+                                true => Ok(lift),
+                                false => Err(UnmappedReason::PartiallyDeleted),
+                            }
+                        });
+                        // INFO/END must follow the chain POS was lifted through
+                        lift.and_then(|lift| match record.info_end {
+                            Some(end) => self
+                                .lift_through(lift.chain_id, end - 1, end)
+                                .map(|x| (record, lift, Some(x))),
+                            None => Ok((record, lift, None)),
+                        })
+                    }
+                    None => Err(UnmappedReason::Malformed),
+                };
+                match lift {
+                    Ok((record, lift, end)) => {
+                        record.write_lifted(&lift, end.as_ref(), &mut lifted)?;
+                        report.lifted += 1;
+                    }
+                    Err(reason) => {
+                        write_rejected(line, reason, &mut rejected)?;
+                        report.unmapped.push(UnmappedRecord {
+                            line: number as u64,
+                            record: line.to_string(),
+                            reason,
+                            blocks: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        if let Some(header) = header {
+            self.write_vcf_header(&header, &mut lifted, &mut rejected)?;
+        }
+        lifted.flush()?;
+        rejected.flush()?;
+        Ok(report)
+    }
+
+    // Private writer of the lifted and rejected VCF headers
+    fn write_vcf_header<L: Write, U: Write>(&self, header: &[String], lifted: &mut L, rejected: &mut U) -> Result<()> {
+        let mut contigs = false;
+        for line in header {
+            if line.starts_with("#CHROM") {
+                if !contigs {
+                    self.write_contigs(lifted)?;
+                }
+                writeln!(
+                    lifted,
+                    "##INFO=<ID={},Number=0,Type=Flag,Description=\"Lifted through a chain on the negative query strand; REF and ALT need reverse-complementing\">",
+                    REVCOMP_FLAG
+                )?;
+                for reason in UnmappedReason::ALL {
+                    writeln!(rejected, "##FILTER=<ID={},Description=\"{}\">", reason.id(), reason)?;
+                }
+            } else if line.starts_with("##contig=") {
+                if !contigs {
+                    self.write_contigs(lifted)?;
+                    contigs = true;
+                }
+                writeln!(rejected, "{}", line)?;
+                continue;
+            }
+            writeln!(lifted, "{}", line)?;
+            writeln!(rejected, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    // Private writer of the `##contig` lines of the query chromosomes
+    fn write_contigs<W: Write>(&self, out: &mut W) -> Result<()> {
+        for (chr, size) in self.query_sizes() {
+            writeln!(out, "##contig=<ID={},length={}>", chr, size)?;
+        }
+        Ok(())
+    }
+}
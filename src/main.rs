@@ -8,7 +8,7 @@ use std::time::Instant;
 fn main() {
     let file:  &str = "/beegfs/projects/project-ymalovichko/toga_extension/duplication_tracing/TOGA2.0_tests/TREE_TESTS/mm10_iqtree_all_50/tmp/input_data/genome_alignment.chain";
     // let file: &str = "nanochain.chain";
-    let acc_chains: Vec<&str> = vec!["1043254"];
+    let acc_chains: Vec<chain::cmap::chain::ChainId> = vec![1043254];
     let chainmap: chain::cmap::map::ChainMap = chain::io::reader::Reader::extract(file, acc_chains).unwrap_or_else(|e| 
         {
             println!("File opening failed {}", e);
@@ -31,7 +31,7 @@ fn main() {
     println!("--------------------------------------------------\n");
 
     println!("Index-based extraction test");
-    let chains_to_extract: Vec<u64> = vec![12, 38, 687002, 1043255, 1043256];
+    let chains_to_extract: Vec<chain::cmap::chain::ChainId> = vec![12, 38, 687002, 1043255, 1043256];
     let extracted_by_index: chain::map::ChainMap = chain::reader::Reader::extract_ix(file, Some(chains_to_extract)).unwrap_or_else(|err|
         {
             panic!("Index-based extraction failed!: {:?}", err);